@@ -1,23 +1,78 @@
+use std::fs;
+
 use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
 use basic_usages::ruf_check_info::UsedRufs;
 
-use cargo_lock::dependency::graph::NodeIndex;
 use petgraph::visit::{self};
 
 use crate::build_config::BuildConfig;
+use crate::cache;
 use crate::dep_manager::DepManager;
 use crate::error::AuditError;
-use crate::extract::extract;
+use crate::extract::{extract, extract_changed};
+use crate::feature_fix::feature_fix;
+use crate::fix_plan::FixPlan;
+use crate::hybrid_fix::hybrid_fix;
+use crate::interactive::{Choice, Decisions};
+use crate::patch_fix::patch_fix;
+use crate::report::{explain_issues, explain_rustc, grade_rufs, show_fail_path};
+use crate::req_fix::relax_local_req;
+use crate::scratch::Scratch;
+use crate::solver::Solver;
+use crate::toolchain::{toolchain_channel, write_toolchain};
+use crate::transaction::LockTransaction;
+use crate::up_fix::UpFix;
 use crate::{error_print, info_print, spec_cargo, warn_print, RUSTV};
 
 /// The main audit functions,
 /// this function shall be called only once, at first layer.
 pub fn audit(mut config: BuildConfig, queit: bool) -> i32 {
+    if let Err(err) = cache::init(config.is_cache(), config.is_clear_cache()) {
+        error_print!(queit, &format!("{err}"));
+        return err.exit_code();
+    }
+
+    // Dry run works on a copy of current workspace, which is dropped last.
+    let _scratch = if config.is_dry_run() {
+        match Scratch::enter() {
+            Ok(scratch) => Some(scratch),
+            Err(err) => {
+                error_print!(queit, &format!("{err}"));
+                return err.exit_code();
+            }
+        }
+    } else {
+        None
+    };
+
+    // Lockfile is restored on any failure.
+    let transaction = match LockTransaction::begin() {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            error_print!(queit, &format!("{err}"));
+            return err.exit_code();
+        }
+    };
+
     // We check ruf usage first
     info_print!(
         queit,
         "Starting",
-        "extract used rufs in current configurations"
+        &format!(
+            "extract used rufs in current configurations, targeting {}rustc {}",
+            if config.is_stable_only() {
+                "stable "
+            } else {
+                ""
+            },
+            config
+                .get_rust_versions()
+                .iter()
+                .map(|rust_version| format!("1.{rust_version}.*"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
     );
     let used_rufs = match extract(&mut config, queit) {
         Ok(used_rufs) => used_rufs,
@@ -28,43 +83,213 @@ pub fn audit(mut config: BuildConfig, queit: bool) -> i32 {
     };
     // println!("[Debug] rufs: {:?}", used_rufs);
 
+    let mut dm = match DepManager::new() {
+        Ok(dm) => dm,
+        Err(err) => {
+            error_print!(queit, &format!("load dependency tree fail: {err}"));
+            return err.exit_code();
+        }
+    };
+
     // We fetch the used features, and then we shall check it
-    if let Err(err) = check_rufs(config, used_rufs, queit) {
+    let origin_rufs = used_rufs.clone();
+    let mut res = check_rufs(&mut config, used_rufs, &mut dm, queit);
+    let rustc = res.as_ref().ok().cloned().flatten();
+    if let Err(err) = cache::flush() {
+        warn_print!(queit, "\tCache", &format!("cannot save cache: {err}"));
+    }
+
+    if config.is_dry_run() {
+        let fixed_lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+        FixPlan::new(dm.fix_steps(), rustc)
+            .show(transaction.origin().unwrap_or_default(), &fixed_lock);
+    }
+
+    // Toolchain file is written last, so that a failure here still rolls back the lockfile.
+    if let Ok(Some(rustc_version)) = res {
+        if config.is_write_toolchain() {
+            let channel = toolchain_channel(rustc_version, config.is_stable_only());
+            if config.is_dry_run() {
+                info_print!(
+                    queit,
+                    "\tToolchain",
+                    &format!("dry run, `{channel}` is not written")
+                );
+            } else {
+                match write_toolchain(&channel) {
+                    Ok(path) => info_print!(
+                        queit,
+                        "\tToolchain",
+                        &format!("`{channel}` is written in `{path}`")
+                    ),
+                    Err(err) => res = Err(err),
+                }
+            }
+        }
+    }
+
+    // In dry run, changes are kept in the scratch copy, so the planned tree is graded below.
+    let finish = if res.is_ok() {
+        transaction.commit();
+        Ok(())
+    } else {
+        transaction.rollback()
+    };
+
+    if let Err(err) = &finish {
+        error_print!(queit, &format!("{err}"));
+    }
+
+    if let Err(err) = res {
         error_print!(queit, &format!("we cannot fix rufs issue: {err}"));
         return err.exit_code();
     }
 
+    if let Err(err) = finish {
+        return err.exit_code();
+    }
+    if config.is_dry_run() {
+        info_print!(
+            queit,
+            "\tRestored",
+            "dry run, `Cargo.lock` is left untouched"
+        );
+    }
+
+    // Usable rufs may still be worth attention, e.g. accepted or incomplete ones.
+    let graded_rufs = if dm.fix_steps().is_empty() && rustc.is_none() {
+        origin_rufs
+    } else {
+        match extract(&mut config, queit) {
+            Ok(used_rufs) => used_rufs,
+            Err(err) => {
+                error_print!(queit, &format!("extract used rufs fail: {err}"));
+                return -1;
+            }
+        }
+    };
+    if let Some(rustc) = rustc {
+        config.set_rust_version(rustc);
+    }
+    info_print!(queit, "Starting", "grading used rufs");
+    let worst = grade_rufs(&config, &graded_rufs, queit);
+    if worst.is_some_and(|grade| grade >= config.get_fail_grade()) {
+        error_print!(
+            queit,
+            &format!(
+                "rufs graded {} or worse are used, see `--fail-on`",
+                config.get_fail_grade()
+            )
+        );
+        // Distinct from failed fixes, the dep tree is usable after all.
+        return -3;
+    }
+
     info_print!(queit, "Finished", "currently no rufs issue found");
-    return 0;
+    0
 }
 
+/// Check ruf usage and try fixing issues, returns the rustc version to use if rustc is changed.
 fn check_rufs(
-    mut config: BuildConfig,
+    config: &mut BuildConfig,
     used_rufs: HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
     queit: bool,
-) -> Result<(), AuditError> {
+) -> Result<Option<u32>, AuditError> {
     info_print!(queit, "Starting", "analyzing used rufs");
 
-    let mut dm = DepManager::new()?;
-
     // check all used rufs
     if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
         // no rufs issue found (but other problem may exists)
-        return Ok(());
+        if config.is_verify() {
+            verify_current(config, queit)?;
+        }
+        return Ok(None);
     }
 
     // or we have to things to fix.
-    if !config.is_quick_fix() {
+    explain_issues(config, &used_rufs, dm, queit);
+
+    // turning off features is the least intrusive fix, so it goes first.
+    let used_rufs = feature_fix(config, used_rufs, dm, queit)?;
+    if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
+        if config.is_verify() {
+            verify_current(config, queit)?;
+        }
+        info_print!(
+            queit,
+            "\tFixed",
+            "all ruf issues are fixed by turning off features"
+        );
+        return Ok(None);
+    }
+
+    // Rustc is not ours to change in multi-toolchain mode.
+    if config.is_hybrid_fix() && !config.is_multi_toolchain() {
+        info_print!(
+            queit,
+            "\tIssue",
+            "try fix by changing rustc and dep tree together"
+        );
+        let snapshot = dm.snapshot()?;
+        let mut hybrid_rufs = used_rufs.clone();
+        let res = hybrid_fix(config, &mut hybrid_rufs, dm, queit).and_then(|rustc_version| {
+            if config.is_verify() {
+                verify_rustc(config, &[rustc_version], queit)
+            } else {
+                Ok(rustc_version)
+            }
+        });
+        match res {
+            Ok(rustc_version) => {
+                info_print!(
+                    queit,
+                    "\tFixed",
+                    &format!(
+                        "all ruf issues are fixed with rustc 1.{rustc_version}.*, usable depenency tree are written in `Cargo.lock`"
+                    )
+                );
+                let rustc_changed = rustc_version != config.get_rust_version();
+                return Ok(rustc_changed.then_some(rustc_version));
+            }
+            Err(e) if e.is_unexpected() => return Err(e),
+            Err(e) => {
+                warn_print!(
+                    queit,
+                    "\tFailed",
+                    &format!("we cannot fix ruf issues through changing rustc and dep tree: {e}")
+                );
+                dm.restore(&snapshot)?;
+            }
+        }
+    }
+
+    // Rustc fix starts from the lockfile before dep tree fix.
+    let snapshot = dm.snapshot()?;
+    if !config.is_quick_fix() || config.is_multi_toolchain() {
         info_print!(queit, "\tIssue", "ruf issues exist, try dep tree fix first");
         // if not quick fix, we will do this, since dep tree fix can be hard and slow
-        let err = match slow_fix(&mut config, used_rufs, &mut dm, queit) {
+        let res = if config.is_solver_fix() {
+            solver_fix(config, used_rufs.clone(), dm, queit)
+        } else {
+            slow_fix(config, used_rufs.clone(), dm, queit)
+        };
+        // A fixed dep tree failing build check is no better than a failed fix.
+        let res = res.and_then(|_| {
+            if config.is_verify() {
+                verify_current(config, queit)
+            } else {
+                Ok(())
+            }
+        });
+        let err = match res {
             Ok(()) => {
                 info_print!(
                     queit,
                     "\tFixed",
                     "all ruf issues are fixed, usable depenency tree are written in `Cargo.lock`"
                 );
-                return Ok(());
+                return Ok(None);
             }
             Err(e) => e,
         };
@@ -74,21 +299,35 @@ fn check_rufs(
             "\tFailed",
             &format!("we cannot fix ruf issues through changing dep tree: {err}")
         );
+        if config.is_multi_toolchain() {
+            // Crates left with issues are those making one dep tree for all toolchains impossible.
+            let used_rufs = extract(config, queit)?;
+            explain_issues(config, &used_rufs, dm, queit);
+            return Err(err);
+        }
+        dm.restore(&snapshot)?;
     }
 
     info_print!(
         queit,
         "\tIssue",
-        "try fix by changing rustc with minimal dep tree"
+        "try fix by changing rustc with issued crates minimized"
     );
-    let err = match fix_with_rustc(&mut config, &mut dm, queit) {
+    let res = fix_with_rustc(config, used_rufs, dm, queit).and_then(|usable_rustc| {
+        if config.is_verify() {
+            verify_rustc(config, &usable_rustc, queit)
+        } else {
+            Ok(usable_rustc[0])
+        }
+    });
+    let err = match res {
         Ok(rustc_version) => {
             info_print!(
                 queit,
                 "\tFixed",
                 &format!("rustc 1.{rustc_version}.* can be used in current configurations")
             );
-            return Ok(());
+            return Ok(Some(rustc_version));
         }
         Err(e) => e,
     };
@@ -98,171 +337,187 @@ fn check_rufs(
         &format!("we cannot fix ruf issues through chaning rustc version: {err}")
     );
 
-    return Err(err);
+    Err(err)
 }
 
-fn up_fix(
-    config: &mut BuildConfig,
-    issued_depnx: NodeIndex,
-    dm: &mut DepManager,
-    queit: bool,
-) -> Result<(), AuditError> {
-    // check which parent shall be updated.
-    let p_req = match dm.req_by(&issued_depnx) {
-        Some(req) => req,
-        None => {
-            // already root crates, up fix fails
-            return Err(AuditError::Functionality(format!(
-                "up fix failed, reaching root"
-            )));
-        }
-    };
+/// Build check with the targeted rustc, in case the fixed dep tree does not compile.
+/// In multi-toolchain mode, each installed one of given toolchains is checked instead.
+fn verify_current(config: &BuildConfig, queit: bool) -> Result<(), AuditError> {
+    if config.is_multi_toolchain() {
+        return verify_toolchains(config, queit);
+    }
 
-    let mut fix_one = false;
-    {
-        let p_pkg = &dm.graph()[p_req.to_owned()];
-        let p_candidates_vers = dm.get_candidates_up_fix(p_req.clone(), issued_depnx.clone())?;
+    // Rustc targeted by the audit, not necessarily the rustup default.
+    let toolchain = toolchain_channel(config.get_rust_version(), config.is_stable_only());
+    if !config.is_toolchain_installed(&toolchain) {
+        warn_print!(
+            queit,
+            "\tVerify",
+            &format!("{toolchain}: not installed, fix is not verified")
+        );
+        return Ok(());
+    }
 
-        let mut usable_vers = vec![];
-        for cad in p_candidates_vers {
-            let used_rufs = config.filter_rufs(p_pkg.name.as_str(), cad.1)?;
-            if config.rufs_usable(&used_rufs) {
-                usable_vers.push(cad.0);
-            }
+    match check_status(config, &toolchain)? {
+        None => {
+            info_print!(queit, "\tVerify", &format!("{toolchain}: passed"));
+            Ok(())
         }
+        Some(err) => {
+            warn_print!(queit, "\tVerify", &format!("{toolchain}: failed, {err}"));
+            Err(AuditError::Functionality(format!(
+                "build check fails with {toolchain}: {err}"
+            )))
+        }
+    }
+}
 
-        let choose = usable_vers.into_iter().max();
-        if let Some(fix_ver) = choose {
-            let name = p_pkg.name.to_string();
-            let ver = p_pkg.version.to_string();
-            let fix_ver = fix_ver.to_string();
-
+/// Build check with each installed toolchain in multi-toolchain mode, all must pass.
+fn verify_toolchains(config: &BuildConfig, queit: bool) -> Result<(), AuditError> {
+    for &rustc_version in config.get_rust_versions() {
+        let toolchain = toolchain_channel(rustc_version, config.is_stable_only());
+        if !config.is_toolchain_installed(&toolchain) {
             info_print!(
                 queit,
-                "\tUpfixing",
-                &format!("downgrading {name}@{ver} to {name}@{fix_ver}")
+                "\tVerify",
+                &format!("{toolchain}: not installed, skipped")
             );
-            // Here previous graph and issue_dep are droped, we have to copy rather than borrow.
-            dm.update_pkg(&name, &ver, &fix_ver)?;
+            continue;
+        }
 
-            fix_one = true;
+        match check_status(config, &toolchain)? {
+            None => info_print!(queit, "\tVerify", &format!("{toolchain}: passed")),
+            Some(err) => {
+                warn_print!(queit, "\tVerify", &format!("{toolchain}: failed, {err}"));
+                return Err(AuditError::Functionality(format!(
+                    "build check fails with {toolchain}: {err}"
+                )));
+            }
         }
     }
 
-    if fix_one {
-        // make sure each time we are making progress
-        return Ok(());
-    }
+    Ok(())
+}
 
-    // or, maybe we have to nested upfix
-    match up_fix(config, p_req, dm, queit) {
-        Ok(_) => {
-            fix_one = true;
+/// Build check with each installed candidate rustc, newest first, returns the first passed one.
+///
+/// If no installed one passes, the newest not installed one is returned unverified.
+fn verify_rustc(
+    config: &BuildConfig,
+    usable_rustc: &[u32],
+    queit: bool,
+) -> Result<u32, AuditError> {
+    let mut unverified = None;
+    for &rustc_version in usable_rustc {
+        let toolchain = toolchain_channel(rustc_version, config.is_stable_only());
+        if !config.is_toolchain_installed(&toolchain) {
+            info_print!(
+                queit,
+                "\tVerify",
+                &format!("{toolchain}: not installed, skipped")
+            );
+            unverified.get_or_insert(rustc_version);
+            continue;
         }
-        Err(e) => {
-            if e.is_unexpected() {
-                return Err(e);
+
+        match check_status(config, &toolchain)? {
+            None => {
+                info_print!(queit, "\tVerify", &format!("{toolchain}: passed"));
+                return Ok(rustc_version);
+            }
+            Some(err) => {
+                warn_print!(queit, "\tVerify", &format!("{toolchain}: failed, {err}"));
             }
         }
     }
 
-    if !fix_one {
-        // up fix fails
-        return Err(AuditError::Functionality(format!(
-            "up fix fails at current layer"
-        )));
+    match unverified {
+        Some(rustc_version) => {
+            warn_print!(
+                queit,
+                "\tVerify",
+                &format!(
+                    "no installed rustc passes build check, rustc 1.{rustc_version}.* is not verified"
+                )
+            );
+            Ok(rustc_version)
+        }
+        None => Err(AuditError::Functionality(
+            "no usable rustc passes build check".to_string(),
+        )),
     }
-
-    Ok(())
 }
 
-fn fix_with_rustc(
-    config: &mut BuildConfig,
-    _dm: &mut DepManager,
-    queit: bool,
-) -> Result<u32, AuditError> {
-    // we restore the dep tree to its release configurations, which is, all oldest.
-    let mut cargo: std::process::Command = spec_cargo(RUSTV);
-    cargo.args(["generate-lockfile", "-Z", "minimal-versions"]);
+/// Run `cargo check` with given toolchain.
+/// Returns the first compiler error if check fails.
+fn check_status(config: &BuildConfig, toolchain: &str) -> Result<Option<String>, AuditError> {
+    let mut cargo = spec_cargo(toolchain);
+    cargo.arg("check");
+    if let Some(cargo_args) = config.get_cargo_args() {
+        cargo.args(cargo_args);
+    }
+
     let output = cargo
         .output()
-        .map_err(|e| AuditError::Unexpected(format!("cannot run cargo generate-lockfile: {e}")))?;
+        .map_err(|e| AuditError::Unexpected(format!("cannot run cargo check: {e}")))?;
 
     if !output.status.success() {
-        return Err(AuditError::Unexpected(format!(
-            "failed to minimal generate-lock"
-        )));
-    }
-    // loop {
-    //     let graph = dm.graph();
-    //     let root = dm.root();
-    //     let mut update = None;
-
-    //     let mut bfs = visit::Bfs::new(&graph, root);
-    //     while let Some(nx) = bfs.next(&graph) {
-    //         let candidates = dm.get_candidates(nx)?;
-    //         if !candidates.is_empty() {
-    //             let oldest = candidates.into_iter().map(|cad| cad.0).min().unwrap();
-    //             update = Some((nx, oldest));
-    //         }
-    //     }
-
-    //     if let Some(update) = update {
-    //         let pkg_name = &graph[update.0].name.to_string();
-    //         let pkg_ver = &graph[update.0].version.to_string();
-    //         // graph drops here
-    //         dm.update_pkg(
-    //             pkg_name.as_str(),
-    //             pkg_ver.to_string().as_str(),
-    //             update.1.to_string().as_str(),
-    //         )?;
-    //     } else {
-    //         break;
-    //     }
-    // }
-
-    // recheck all used rufs
-    let used_rufs = extract(config, queit)?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
 
-    let mut usable_rustc = HashSet::from_iter(0..=63);
-    for rufs in used_rufs.into_values() {
-        let rustc_versions = config.usable_rustc_for_rufs(&rufs);
-        if rustc_versions.is_empty() {
-            return Err(AuditError::Functionality(
-                "cannot find usable rustc version for current configurations".to_string(),
-            ));
-        }
-        usable_rustc = usable_rustc
-            .intersection(&rustc_versions)
-            .cloned()
-            .collect();
+        let err = stderr
+            .lines()
+            .find(|line| line.trim_start().starts_with("error"))
+            .unwrap_or("unknown error");
+
+        return Ok(Some(err.to_string()));
     }
 
-    usable_rustc.iter().max().cloned().ok_or_else(|| {
-        AuditError::Functionality(
-            "cannot find usable rustc version for current configurations".to_string(),
-        )
-    })
+    Ok(None)
 }
 
-/// We do minimal tree and find a usable rustc.
-/// The lockfile may not be buildable, but can prove fixability in some degree.
-fn quick_fix(config: &mut BuildConfig, queit: bool) -> Result<u32, AuditError> {
-    // we restore the dep tree to its minimal configurations, which is, all oldest.
-    let mut cargo: std::process::Command = spec_cargo(RUSTV);
-    cargo.args(["generate-lockfile", "-Z", "minimal-versions"]);
-    let output = cargo
-        .output()
-        .map_err(|e| AuditError::Unexpected(format!("cannot run cargo generate-lockfile: {e}")))?;
+pub fn change_action(cur_ver: &Version, fix_ver: &Version) -> &'static str {
+    if fix_ver > cur_ver {
+        "upgrading"
+    } else {
+        "downgrading"
+    }
+}
 
-    if !output.status.success() {
-        return Err(AuditError::Unexpected(format!(
-            "failed to generate a minimal dependency tree"
-        )));
+/// Minimize subgraphs of crates with ruf issues, and recheck used rufs.
+/// Unrelated packages are kept as locked.
+fn minimize_issued(
+    config: &mut BuildConfig,
+    used_rufs: &mut HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<(), AuditError> {
+    let issued: HashSet<String> = used_rufs
+        .iter()
+        .filter(|(_, rufs)| !config.rufs_usable(rufs))
+        .map(|(crate_name, _)| crate_name.clone())
+        .collect();
+
+    for name_ver in dm.minimal_subtrees(&issued)? {
+        warn_print!(
+            queit,
+            "\tMinimize",
+            &format!("cannot change {name_ver} to its oldest version, kept as locked")
+        );
     }
 
-    // recheck all used rufs
-    let used_rufs = extract(config, queit)?;
+    extract_changed(config, dm, used_rufs, queit)
+}
+
+/// Fix with issued crates minimized and changing rustc, returns usable rustc versions, newest first.
+fn fix_with_rustc(
+    config: &mut BuildConfig,
+    mut used_rufs: HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<Vec<u32>, AuditError> {
+    // older versions use older rufs, which are more likely usable in some rustc.
+    minimize_issued(config, &mut used_rufs, dm, queit)?;
+    explain_rustc(config, &used_rufs, queit);
 
     let mut usable_rustc = HashSet::from_iter(0..=63);
     for rufs in used_rufs.into_values() {
@@ -278,17 +533,36 @@ fn quick_fix(config: &mut BuildConfig, queit: bool) -> Result<u32, AuditError> {
             .collect();
     }
 
-    usable_rustc.iter().max().cloned().ok_or_else(|| {
-        AuditError::Functionality(
+    if usable_rustc.is_empty() {
+        return Err(AuditError::Functionality(
             "cannot find usable rustc version for current configurations".to_string(),
-        )
-    })
+        ));
+    }
+
+    let mut usable_rustc: Vec<u32> = usable_rustc.into_iter().collect();
+    usable_rustc.sort_unstable_by(|a, b| b.cmp(a));
+    Ok(usable_rustc)
 }
 
 fn slow_fix(
+    config: &mut BuildConfig,
+    used_rufs: HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<(), AuditError> {
+    let mut decisions = Decisions::new(config)?;
+    let res = slow_fix_with(config, used_rufs, dm, &mut decisions, queit);
+    // Decisions are saved even if aborted, so that they can be replayed and continued.
+    decisions.save(config, queit)?;
+
+    res
+}
+
+fn slow_fix_with(
     config: &mut BuildConfig,
     mut used_rufs: HashMap<String, UsedRufs>,
     dm: &mut DepManager,
+    decisions: &mut Decisions,
     queit: bool,
 ) -> Result<(), AuditError> {
     // This algo will ends, because we have a finite number of crates
@@ -296,6 +570,9 @@ fn slow_fix(
 
     // println!("[Debug - fix_with_dep] used_rufs: {used_rufs:?}");
 
+    // Crates we fail to fix, they are skipped so that others can still be fixed.
+    let mut unfixable = HashSet::default();
+
     loop {
         // We do bfs and thus fix problems up to down.
         let graph = dm.graph();
@@ -306,8 +583,11 @@ fn slow_fix(
         while let Some(nx) = bfs.next(&graph) {
             let node = &graph[nx];
             // println!("[Debug - fix_with_dep] check package {}", node.name);
-            if let Some(rufs) = used_rufs.get(&node.name.as_str().replace("-", "_")) {
-                if !config.rufs_usable(&rufs) {
+            if unfixable.contains(&format!("{}@{}", node.name, node.version)) {
+                continue;
+            }
+            if let Some(rufs) = used_rufs.get(&node.name.as_str().replace('-', "_")) {
+                if !config.rufs_usable(rufs) {
                     issued_depnx = Some(nx);
                     break;
                 }
@@ -315,8 +595,22 @@ fn slow_fix(
         }
 
         if issued_depnx.is_none() {
-            // No rufs issue found (but other problem may exists).
-            return Ok(());
+            if unfixable.is_empty() {
+                // No rufs issue found (but other problem may exists).
+                return Ok(());
+            }
+
+            let mut unfixable: Vec<String> = unfixable.into_iter().collect();
+            unfixable.sort();
+            let reason = if config.is_stable_only() {
+                "cannot avoid nightly features"
+            } else {
+                "cannot avoid unusable rufs"
+            };
+            return Err(AuditError::Functionality(format!(
+                "crates {reason}: {}",
+                unfixable.join(", ")
+            )));
         }
 
         // We found a ruf issue
@@ -330,15 +624,31 @@ fn slow_fix(
         );
 
         // Canditate versions, restricted by semver, no rufs checks beed done.
-        let candidate_vers = dm.get_candidates(issued_depnx)?;
+        let issued = format!("{}@{}", issued_dep.name, issued_dep.version);
+        let candidate_vers = match dm.get_candidates(issued_depnx) {
+            Ok(candidate_vers) => candidate_vers,
+            Err(AuditError::Candidate(e)) => {
+                // Skip this crate, others may still be fixed.
+                warn_print!(queit, "\tFailed", &format!("cannot fix {issued}: {e}"));
+                unfixable.insert(issued);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
 
-        // And here we check rufs.
+        // And here we check rufs, all candidates at once.
+        let (cad_vers, cad_rufs): (Vec<_>, Vec<_>) = candidate_vers.into_iter().unzip();
+        let jobs = cad_vers
+            .iter()
+            .zip(cad_rufs)
+            .map(|(ver, rufs)| (issued_dep.name.as_str(), ver, rufs, None))
+            .collect();
         let mut usable_vers = vec![];
-        for cad in candidate_vers {
-            let used_rufs = config.filter_rufs(issued_dep.name.as_str(), cad.1)?;
-            // println!("[Debug - fix_with_dep] filter {} - {:?}", cad.0.to_string(), used_rufs);
+        let filtered = config.filter_rufs_many(jobs)?;
+        for (ver, used_rufs) in cad_vers.into_iter().zip(filtered) {
+            // println!("[Debug - fix_with_dep] filter {} - {:?}", ver.to_string(), used_rufs);
             if config.rufs_usable(&used_rufs) {
-                usable_vers.push(cad.0);
+                usable_vers.push(ver);
             }
         }
 
@@ -349,99 +659,146 @@ fn slow_fix(
         //         .map(|v| v.to_string())
         //         .collect::<Vec<String>>()
         // );
-        // invoke donw fix first.
-        let choose = usable_vers.into_iter().max();
+        // invoke donw fix first, with preferred candidates first.
+        config.rank_candidates(&issued_dep.version, &mut usable_vers);
+        let choose = if usable_vers.is_empty() {
+            None
+        } else {
+            let unusable_rufs = used_rufs
+                .get(&issued_dep.name.as_str().replace('-', "_"))
+                .map(|rufs| config.unusable_rufs(rufs))
+                .unwrap_or_default();
+            match decisions.decide(&issued, &unusable_rufs, &usable_vers, queit)? {
+                Choice::Update(fix_ver) => Some(fix_ver),
+                Choice::Skip => {
+                    warn_print!(queit, "\tSkipped", &format!("{issued} is skipped"));
+                    unfixable.insert(issued);
+                    continue;
+                }
+            }
+        };
         if let Some(fix_ver) = choose {
             let name = issued_dep.name.to_string();
             let ver = issued_dep.version.to_string();
+            let action = change_action(&issued_dep.version, &fix_ver);
             let fix_ver = fix_ver.to_string();
 
             info_print!(
                 queit,
                 "\tFixing",
-                &format!("downgrading {name}@{ver} to {name}@{fix_ver}")
+                &format!("{action} {name}@{ver} to {name}@{fix_ver}")
             );
 
             // Here previous graph and issue_dep are droped, we have to copy rather than borrow.
-            dm.update_pkg(&name, &ver, &fix_ver)?;
+            match dm.update_pkg(&name, &ver, &fix_ver) {
+                Ok(()) => {}
+                Err(AuditError::Candidate(e)) => {
+                    // Skip this crate, others may still be fixed.
+                    warn_print!(queit, "\tFailed", &format!("cannot fix {issued}: {e}"));
+                    unfixable.insert(issued);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
 
             info_print!(queit, "\tFixing", "rechecking ruf issues");
-            used_rufs = extract(config, queit)?;
+            extract_changed(config, dm, &mut used_rufs, queit)?;
         } else {
             // No usable version, maybe parents need relax, we do up fix.
-            warn_print!(queit, "\tFixing", &format!("no candidates found, do upfix"));
-            match up_fix(config, issued_depnx, dm, queit) {
+            warn_print!(queit, "\tFixing", "no candidates found, do upfix");
+            let mut path = vec![];
+            match UpFix::new(config, dm, issued_depnx, queit).search(&mut path) {
                 Ok(_) => {
                     info_print!(queit, "\tUpfixing", "rechecking ruf issues");
-                    used_rufs = extract(config, queit)?;
+                    extract_changed(config, dm, &mut used_rufs, queit)?;
                 }
                 Err(e) => {
-                    if !e.is_unexpected() {
-                        // TODO: Print fail paths
+                    if e.is_unexpected() {
+                        return Err(e);
+                    }
+                    warn_print!(queit, "\tFailed", &format!("cannot fix {issued}: {e}"));
+                    show_fail_path(&issued, &path, queit);
+                    if relax_local_req(config, dm, &path, queit)? {
+                        info_print!(queit, "\tFixing", "rechecking ruf issues");
+                        extract_changed(config, dm, &mut used_rufs, queit)?;
+                        continue;
+                    }
+                    if patch_fix(config, dm, &issued, queit)? {
+                        info_print!(queit, "\tPatching", "rechecking ruf issues");
+                        extract_changed(config, dm, &mut used_rufs, queit)?;
+                        let crate_name = issued.split('@').next().unwrap_or(&issued);
+                        if let Some(rufs) = used_rufs.get(&crate_name.replace('-', "_")) {
+                            if !config.rufs_usable(rufs) {
+                                warn_print!(
+                                    queit,
+                                    "\tFailed",
+                                    &format!("patched {issued} still uses unusable rufs")
+                                );
+                                unfixable.insert(issued);
+                            }
+                        }
+                        continue;
                     }
-                    return Err(e);
+                    unfixable.insert(issued);
                 }
             }
         }
     }
 }
 
-/*
-pub fn test(mut config: BuildConfig) -> i32 {
-    // config.set_newer_fix(true);
-
-    // we test no fix first
-    fn show_result(result: (bool, bool)) {
-        println!("\n===({},{})===\n", result.0, result.1);
-    }
+/// Fix dep tree by solving all packages' versions together.
+/// Unlike [`slow_fix`], this either finds a usable dep tree, or proves there is none.
+fn solver_fix(
+    config: &mut BuildConfig,
+    mut used_rufs: HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<(), AuditError> {
+    // Changing versions may introduce new crates, which are unknown to the solver,
+    // so we solve again until the tree is usable, or we meet a tree seen before.
+    let mut seen_trees = HashSet::default();
 
-    let mut result = (true, true);
-    info_print!(false, "Test 1", "no fix ruf usage");
-    let used_rufs = match extract(&mut config, false) {
-        Ok(used_rufs) => used_rufs,
-        Err(err) => {
-            error_print!(false, &format!("extract used rufs fail: {err}"));
-            return 1;
+    loop {
+        if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
+            return Ok(());
         }
-    };
-
-    if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
-        info_print!(false, "Test 1", "ruf usage ok");
-        show_result(result);
-        return 0;
-    }
-    result.0 = false;
-
-    info_print!(false, "Test 2", "not rustc fix, only stepping dep tree fix");
 
-    let mut dm = match DepManager::new() {
-        Ok(dm) => dm,
-        Err(e) => {
-            error_print!(false, &format!("fix failed due to: {e}"));
-            return 1;
+        let mut tree: Vec<String> = dm
+            .graph()
+            .node_weights()
+            .map(|pkg| format!("{}@{}", pkg.name, pkg.version))
+            .collect();
+        tree.sort();
+        if !seen_trees.insert(tree) {
+            return Err(AuditError::Functionality(
+                "solver cannot make progress, dep tree unchanged".to_string(),
+            ));
         }
-    };
 
-    let err = match fix_with_dep(&mut config, used_rufs, &mut dm, false) {
-        Ok(()) => {
-            info_print!(false, "Test 2", "issue fixed");
-            show_result(result);
-            return 0;
-        }
-        Err(e) => e,
-    };
+        info_print!(
+            queit,
+            "\tSolving",
+            "searching usable versions for all packages"
+        );
+        let updates = Solver::new(config, dm, &used_rufs).solve()?;
 
-    if let AuditError::Unexpected(e) = err {
-        error_print!(false, &format!("fix failed due to: {e}"));
-        return 1;
-    } else {
-        result.1 = false;
-        info_print!(false, "Failed", "cannot fix ruf issues");
-        show_result(result);
-        return 2;
+        let updates: Vec<(String, String, String)> = updates
+            .into_iter()
+            .map(|(name, ver, fix_ver)| {
+                info_print!(
+                    queit,
+                    "\tSolving",
+                    &format!("changing {name}@{ver} to {name}@{fix_ver}")
+                );
+                (name, ver.to_string(), fix_ver.to_string())
+            })
+            .collect();
+        dm.update_pkgs(&updates)?;
+
+        info_print!(queit, "\tSolving", "rechecking ruf issues");
+        extract_changed(config, dm, &mut used_rufs, queit)?;
     }
 }
-*/
 
 // test without build check
 pub fn test(mut config: BuildConfig) -> i32 {
@@ -496,7 +853,7 @@ pub fn test(mut config: BuildConfig) -> i32 {
         cargo.output().map(|output| output.status.success()),
         Err(_) | Ok(false)
     ) {
-        error_print!(false, &format!("cannot generate minimal dep tree"));
+        error_print!(false, "cannot generate minimal dep tree");
         return 1;
     }
 
@@ -535,153 +892,5 @@ pub fn test(mut config: BuildConfig) -> i32 {
 
     info_print!(false, "Failed", "cannot fix ruf issues");
     show_result(result);
-    return 2;
-}
-
-// test with build check
-/*
-pub fn test(mut config: BuildConfig) -> i32 {
-    // we test no fix first
-    fn show_result(result: (bool, bool, bool, bool)) {
-        println!(
-            "\n===({},{},{},{})===\n",
-            result.0, result.1, result.2, result.3
-        );
-    }
-
-    let mut result = (true, true, true, true);
-
-    info_print!(false, "Test 1", "no fix ruf usage");
-    let used_rufs = match extract(&mut config, true) {
-        Ok(used_rufs) => used_rufs,
-        Err(err) => {
-            error_print!(false, &format!("extract rufs fail: {err}"));
-            return -1;
-        }
-    };
-
-    if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
-        if let Some(err) = check_status(&config) {
-            warn_print!(false, "Test 1", &format!("check not ok, {err}"));
-        } else {
-            warn_print!(false, "Test 1", "ruf usage ok");
-            show_result(result);
-            return 0;
-        }
-    } else {
-        warn_print!(false, "Test 1", "ruf not ok");
-    }
-    result.0 = false;
-
-    info_print!(false, "Test 2", "no dep tree fix, only rustc fix");
-    let mut usable_rustc = HashSet::from_iter(0..=63);
-    for rufs in used_rufs.into_values() {
-        let ur = config.usable_rustc_for_rufs(&rufs);
-        usable_rustc = usable_rustc.intersection(&ur).cloned().collect();
-    }
-
-    if !usable_rustc.is_empty() {
-        let max = usable_rustc.iter().max().cloned().unwrap();
-        config.update_rust_version(max);
-
-        if let Some(err) = check_status(&config) {
-            warn_print!(false, "Test 2", &format!("check not ok, {err}"));
-        } else {
-            warn_print!(false, "Test 2", &format!("rustc fix: {:?}", max));
-            show_result(result);
-            return 0;
-        }
-    } else {
-        warn_print!(false, "Test 2", "no usable rustc");
-    }
-    result.1 = false;
-    config.restore_rust_version();
-
-    info_print!(false, "Test 3", "no rustc fix, only min dep tree");
-    let mut cargo = spec_cargo(RUSTV);
-    cargo.args(["generate-lockfile", "-Z", "minimal-versions"]);
-    if matches!(
-        cargo.output().map(|output| output.status.success()),
-        Err(_) | Ok(false)
-    ) {
-        error_print!(false, &format!("cannot generate minimal dep tree"));
-        return -1;
-    }
-
-    let used_rufs = match extract(&mut config, true) {
-        Ok(used_rufs) => used_rufs,
-        Err(err) => {
-            error_print!(false, &format!("extract used rufs fail: {err}"));
-            return -1;
-        }
-    };
-
-    if used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs)) {
-        if let Some(err) = check_status(&config) {
-            warn_print!(false, "Test 3", &format!("check not ok, {err}"));
-        } else {
-            warn_print!(false, "Test 3", "ruf usage ok");
-            show_result(result);
-            return 0;
-        }
-    } else {
-        warn_print!(false, "Test 3", "ruf not ok");
-    }
-    result.2 = false;
-
-    info_print!(false, "Test 4", "min dep tree, and rustc fix");
-    let mut usable_rustc = HashSet::from_iter(0..=63);
-    for rufs in used_rufs.into_values() {
-        let ur = config.usable_rustc_for_rufs(&rufs);
-        usable_rustc = usable_rustc.intersection(&ur).cloned().collect();
-    }
-
-    if !usable_rustc.is_empty() {
-        let max = usable_rustc.iter().max().cloned().unwrap();
-        config.update_rust_version(max);
-
-        if let Some(err) = check_status(&config) {
-            warn_print!(false, "Test 4", &format!("check not ok, {err}"));
-        } else {
-            warn_print!(false, "Test 4", &format!("rustc fix: {:?}", max));
-            show_result(result);
-            return 0;
-        }
-    } else {
-        warn_print!(false, "Test 4", "no usable rustc");
-    }
-    result.3 = false;
-
-    error_print!(false, "cannot fix ruf issues");
-    show_result(result);
-
-    return -2;
-}
-
-fn check_status(config: &BuildConfig) -> Option<String> {
-    let mut cargo = spec_cargo(config.get_rustc_spec());
-    cargo.arg("check");
-    if let Some(cargo_args) = config.get_cargo_args() {
-        cargo.args(cargo_args);
-    }
-
-    let output = match cargo.output() {
-        Ok(o) => o,
-        Err(e) => return Some(format!("{e}")),
-    };
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-
-        let err = stderr
-            .lines()
-            .into_iter()
-            .find(|line| line.trim_start().starts_with("error"))
-            .unwrap_or("unknown error");
-
-        return Some(err.to_string());
-    }
-
-    None
+    2
 }
-*/
@@ -1,14 +1,16 @@
 use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
 use basic_usages::external::serde_json;
-use basic_usages::ruf_check_info::{CondRufs, UsedRufs};
+use basic_usages::ruf_check_info::{CondRufs, RufStatus, ScanJob, ScanResult, UsedRufs};
 use basic_usages::ruf_lifetime::{get_ruf_all_status, get_ruf_status, RUSTC_VER_NUM};
 use std::env;
 use std::io::Write;
 use std::process::{Command, Stdio};
+use std::thread;
 
-use super::BuildConfig;
+use super::{BuildConfig, CandidatePolicy, Grade};
+use crate::cache;
 use crate::error::AuditError;
-use crate::RE_USEDFEATS;
 use crate::{scanner, RE_RUSTC_VRESION};
 
 impl<'short, 'long: 'short> BuildConfig<'long> {
@@ -33,9 +35,9 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
                 .lines()
                 .find(|line| line.starts_with("Default host:"))
                 .ok_or_else(|| {
-                    AuditError::Unexpected(format!(
-                        "cannot build BuildConfig, fail to fetch rustup default host"
-                    ))
+                    AuditError::Unexpected(
+                        "cannot build BuildConfig, fail to fetch rustup default host".to_string(),
+                    )
                 })?;
 
             host_line[13..].trim().to_string()
@@ -46,9 +48,9 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
                 .lines()
                 .find(|line| line.starts_with("rustup home:"))
                 .ok_or_else(|| {
-                    AuditError::Unexpected(format!(
-                        "cannot build BuildConfig, fail to fetch rustup home"
-                    ))
+                    AuditError::Unexpected(
+                        "cannot build BuildConfig, fail to fetch rustup home".to_string(),
+                    )
                 })?;
 
             rustup_home_line[13..].trim().to_string()
@@ -58,24 +60,40 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
             cargo_home
         } else {
             env::var("HOME").map_err(|_| {
-                AuditError::Unexpected(format!(
-                    "cannot build BuildConfig, fail to fetch cargo home"
-                ))
+                AuditError::Unexpected(
+                    "cannot build BuildConfig, fail to fetch cargo home".to_string(),
+                )
             })? + "/.cargo"
         };
 
-        let rust_version = RE_RUSTC_VRESION
-            .captures(&profiles)
-            .ok_or_else(|| {
-                AuditError::Unexpected(format!(
-                    "cannot build BuildConfig, fail to fetch rustc version"
-                ))
-            })?
+        // Newer rustup no longer shows rustc version in profiles, we ask rustc instead.
+        let rustc_info = if RE_RUSTC_VRESION.is_match(&profiles) {
+            profiles.to_string()
+        } else {
+            Command::new("rustc")
+                .arg("--version")
+                .output()
+                .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+                .unwrap_or_default()
+        };
+
+        let caps = RE_RUSTC_VRESION.captures(&rustc_info).ok_or_else(|| {
+            AuditError::Unexpected(
+                "cannot build BuildConfig, fail to fetch rustc version".to_string(),
+            )
+        })?;
+        let rust_version = caps
             .get(1)
             .expect("Fatal, resolve rustc version fails")
             .as_str()
             .parse::<u32>()
             .expect("Fatal, parse rustc version fails");
+        // Rustc newer than our lifetime table cannot be audited, unless another one is chosen,
+        // so the latest one recorded only stands in until then.
+        let unsupported_rustc = ((rust_version as usize) >= RUSTC_VER_NUM).then_some(rust_version);
+        let rust_version = rust_version.min(RUSTC_VER_NUM as u32 - 1);
+        // No rufs can be used on stable rustc, except stabilized ones.
+        let stable_only = caps.get(2).is_none();
 
         let crates_cfgs = HashMap::default();
 
@@ -84,11 +102,36 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
             rustup_home,
             cargo_home,
             rust_version,
+            unsupported_rustc,
+            rust_versions: vec![],
+            stable_only,
 
             cargo_args: None,
             crates_cfgs,
 
             quick_fix: false,
+            solver_fix: false,
+            hybrid_fix: false,
+            candidate_policy: CandidatePolicy::PreferDowngrade,
+            up_fix_depth: 10,
+            up_fix_budget: 300,
+            dry_run: false,
+            write_toolchain: false,
+            verify: false,
+            feature_fix: false,
+            relax_reqs: false,
+            assume_yes: false,
+            patches: HashMap::default(),
+            vendor_dir: None,
+            interactive: false,
+            choices_file: "ruf_audit_choices.json".to_string(),
+            replay_file: None,
+            active_grade: Grade::Info,
+            incomplete_grade: Grade::Warning,
+            accepted_grade: Grade::Warning,
+            fail_grade: Grade::Error,
+            cache: true,
+            clear_cache: false,
             verbose: false,
             test: false,
         })
@@ -102,120 +145,354 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
         self.crates_cfgs.insert(crate_name, cfgs);
     }
 
+    /// Set the rustc version, against which ruf usability is judged.
+    pub fn set_rust_version(&mut self, rust_version: u32) {
+        assert!((rust_version as usize) < RUSTC_VER_NUM);
+        self.rust_version = rust_version;
+        self.unsupported_rustc = None;
+    }
+
+    /// Rust version of default toolchain, if it is newer than our lifetime table and no other is chosen.
+    #[inline]
+    pub fn get_unsupported_rustc(&self) -> Option<u32> {
+        self.unsupported_rustc
+    }
+
+    pub fn get_rust_version(&self) -> u32 {
+        self.rust_version
+    }
+
+    /// Set several rustc versions, all of which rufs must be usable with.
+    /// The newest one becomes the current rust version.
+    pub fn set_rust_versions(&mut self, mut rust_versions: Vec<u32>) {
+        rust_versions.sort_unstable();
+        rust_versions.dedup();
+        if let Some(newest) = rust_versions.last() {
+            self.set_rust_version(*newest);
+        }
+        self.rust_versions = rust_versions;
+    }
+
+    /// Rustc versions against which ruf usability is judged.
+    pub fn get_rust_versions(&self) -> &[u32] {
+        if self.rust_versions.is_empty() {
+            std::slice::from_ref(&self.rust_version)
+        } else {
+            &self.rust_versions
+        }
+    }
+
+    /// Whether rufs must be usable with several rustc versions.
+    pub fn is_multi_toolchain(&self) -> bool {
+        !self.rust_versions.is_empty()
+    }
+
+    /// Whether the crate is built in current configurations.
+    pub fn is_built(&self, crate_name: &str) -> bool {
+        self.crates_cfgs.contains_key(&crate_name.replace('-', "_"))
+    }
+
+    /// Cargo features enabled when building the crate.
+    pub fn enabled_features(&self, crate_name: &str) -> Vec<String> {
+        let Some(cfgs) = self.crates_cfgs.get(&crate_name.replace('-', "_")) else {
+            return vec![];
+        };
+
+        let mut features: Vec<String> = cfgs
+            .iter()
+            .filter_map(|cfg| {
+                let cfg: String = serde_json::from_str(&format!("\"{cfg}\"")).ok()?;
+                cfg.strip_prefix("feature=\"")?
+                    .strip_suffix('"')
+                    .map(|feature| feature.to_string())
+            })
+            .collect();
+        features.sort();
+        features
+    }
+
     pub fn update_cargo_args(&mut self, cargo_args: &'long [String]) {
         self.cargo_args = Some(cargo_args)
     }
 
     /// Filter used rufs in current configurations.
     /// This step need support of our database.
-    pub fn filter_rufs(&self, crate_name: &str, rufs: CondRufs) -> Result<UsedRufs, AuditError> {
-        let mut content = String::new();
-        let mut used_rufs = UsedRufs::empty();
-
-        for ruf in rufs.into_iter() {
-            if let Some(cond) = &ruf.cond {
-                content.push_str(&format!(
-                    "#![cfg_attr({}, feature({}))]\n",
-                    cond, ruf.feature
-                ))
-            } else {
-                used_rufs.push(ruf.feature);
-            }
-        }
+    pub fn filter_rufs(
+        &self,
+        crate_name: &str,
+        ver: &Version,
+        rufs: CondRufs,
+    ) -> Result<UsedRufs, AuditError> {
+        Ok(self
+            .filter_rufs_many(vec![(crate_name, ver, rufs, None)])?
+            .pop()
+            .expect("Fatal, no filtered rufs"))
+    }
 
-        // use scanner to check cfg rufs
-        if !content.is_empty() {
-            // We need a main or the command may fails
-            content.push_str("fn main(){}\n");
-
-            let cfgs = self
-                .crates_cfgs
-                .get(&crate_name.replace("-", "_"))
-                .expect(&format!("Fatal, no cfgs found with {crate_name}"));
-
-            // println!("[Debug - filter_rufs] content: \n{content}\ncfg: {cfgs:?}");
-            let mut scanner = scanner();
-            scanner.arg("--");
-            for cfg in cfgs {
-                let cfg: String = serde_json::from_str(&format!("\"{cfg}\"")).unwrap();
-                scanner.args(["--cfg", &cfg]);
+    /// Filter used rufs of many crate versions at once, optionally without a cargo feature,
+    /// results are in the same order.
+    ///
+    /// Conditional rufs are checked by scanners in batch mode, whose jobs are spread
+    /// across a worker pool sized to the machine, so that the compiler is loaded once per worker.
+    /// Scanned results are cached by crate version and cfgs.
+    pub fn filter_rufs_many(
+        &self,
+        jobs: Vec<(&str, &Version, CondRufs, Option<&str>)>,
+    ) -> Result<Vec<UsedRufs>, AuditError> {
+        let mut results = Vec::with_capacity(jobs.len());
+        let mut scan_jobs = vec![];
+        // cache keys of scan jobs, by job id.
+        let mut scan_keys = HashMap::default();
+
+        for (id, (crate_name, ver, rufs, feature)) in jobs.into_iter().enumerate() {
+            let removed = feature.map(|feature| {
+                format!("feature=\"{feature}\"")
+                    .escape_default()
+                    .to_string()
+            });
+            let mut src = String::new();
+            let mut used_rufs = UsedRufs::empty();
+
+            for ruf in rufs.into_iter() {
+                if let Some(cond) = &ruf.cond {
+                    src.push_str(&format!(
+                        "#![cfg_attr({}, feature({}))]\n",
+                        cond, ruf.feature
+                    ))
+                } else {
+                    used_rufs.push(ruf.feature);
+                }
             }
-            scanner.arg("-");
-
-            // println!("[Debug - filter_rufs] scanner {:?}", scanner);
-            let scanner = scanner
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .env("LD_LIBRARY_PATH", self.get_rustlib_path())
-                .spawn()
-                .expect("Fatal, cannot spawn scanner");
-
-            {
-                let mut stdin = scanner
-                    .stdin
-                    .as_ref()
-                    .expect("Fatal, cannot fetch scanner stdin");
-                stdin
-                    .write_all(content.as_bytes())
-                    .expect("Fatal, cannot write to scanner stdin");
+
+            // use scanner to check cfg rufs
+            if !src.is_empty() {
+                // We need a main or the command may fails
+                src.push_str("fn main(){}\n");
+
+                let cfgs = self
+                    .crates_cfgs
+                    .get(&crate_name.replace('-', "_"))
+                    .unwrap_or_else(|| panic!("Fatal, no cfgs found with {crate_name}"));
+                let cfgs: Vec<String> = cfgs
+                    .iter()
+                    .filter(|cfg| Some(*cfg) != removed.as_ref())
+                    .map(|cfg| serde_json::from_str(&format!("\"{cfg}\"")).unwrap())
+                    .collect();
+
+                let key = cache::filtered_key(ver, &cfgs);
+                if let Some(cached) = cache::get_filtered(crate_name, &key) {
+                    used_rufs.extend(cached);
+                } else {
+                    scan_keys.insert(id, (crate_name, key));
+                    scan_jobs.push(ScanJob { id, cfgs, src });
+                }
             }
+            results.push(used_rufs);
+        }
 
-            let output = scanner
-                .wait_with_output()
-                .expect("Fatal, cannot fetch scanner output");
+        if scan_jobs.is_empty() {
+            return Ok(results);
+        }
 
-            if !output.status.success() {
-                return Err(AuditError::Unexpected(format!(
-                    "cannot check candidate rufs: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                )));
-            }
+        let workers = thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(scan_jobs.len());
+        let chunk_size = scan_jobs.len().div_ceil(workers);
+        let scanned = thread::scope(|s| {
+            let workers: Vec<_> = scan_jobs
+                .chunks(chunk_size)
+                .map(|jobs| s.spawn(|| self.run_scanner(jobs)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Fatal, scanner worker panics"))
+                .collect::<Result<Vec<_>, AuditError>>()
+        })?;
 
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            // println!("[Debug - filter_rufs] stdout: {stdout}");
-            if let Some(caps) = RE_USEDFEATS.captures(&stdout) {
-                used_rufs.extend(UsedRufs::from(
-                    caps.get(1)
-                        .expect("Fatal, cannot filter ruf usage")
-                        .as_str(),
-                ));
-            }
+        for result in scanned.into_iter().flatten() {
+            let used_rufs = result
+                .used_rufs
+                .map_err(|e| AuditError::Unexpected(format!("cannot check candidate rufs: {e}")))?;
+            let (crate_name, key) = scan_keys
+                .remove(&result.id)
+                .expect("Fatal, unknown scan job");
+            cache::put_filtered(crate_name, key, used_rufs.clone());
+            results[result.id].extend(used_rufs);
         }
 
-        Ok(used_rufs)
+        Ok(results)
+    }
+
+    /// Run scanner in batch mode over the jobs.
+    fn run_scanner(&self, jobs: &[ScanJob]) -> Result<Vec<ScanResult>, AuditError> {
+        let mut input = String::new();
+        for job in jobs {
+            input.push_str(&serde_json::to_string(job).expect("Fatal, cannot serialize scan job"));
+            input.push('\n');
+        }
+
+        let mut scanner = scanner();
+        scanner.args(["--batch", "--"]);
+        // println!("[Debug - filter_rufs] scanner {:?}", scanner);
+        let scanner = scanner
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .env("LD_LIBRARY_PATH", self.get_rustlib_path())
+            .spawn()
+            .expect("Fatal, cannot spawn scanner");
+
+        {
+            let mut stdin = scanner
+                .stdin
+                .as_ref()
+                .expect("Fatal, cannot fetch scanner stdin");
+            stdin
+                .write_all(input.as_bytes())
+                .expect("Fatal, cannot write to scanner stdin");
+        }
+
+        let output = scanner
+            .wait_with_output()
+            .expect("Fatal, cannot fetch scanner output");
+
+        if !output.status.success() {
+            return Err(AuditError::Unexpected(format!(
+                "cannot check candidate rufs: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        // println!("[Debug - filter_rufs] stdout: {stdout}");
+        let results: Vec<ScanResult> = stdout
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        if results.len() != jobs.len() {
+            return Err(AuditError::Unexpected(format!(
+                "cannot check candidate rufs: {} of {} jobs scanned",
+                results.len(),
+                jobs.len()
+            )));
+        }
+
+        Ok(results)
     }
 
     /// Check whether rufs is usable in current configurations.
     pub fn rufs_usable(&self, rufs: &UsedRufs) -> bool {
         assert!(self.rust_version < basic_usages::ruf_lifetime::RUSTC_VER_NUM as u32);
-        if rufs
-            .iter()
-            .filter(|ruf| !get_ruf_status(ruf, self.rust_version).is_usable())
-            .count()
-            > 0
-        {
+        if rufs.iter().filter(|ruf| !self.ruf_usable(ruf)).count() > 0 {
             return false;
         }
 
-        return true;
+        true
+    }
+
+    /// Rufs not usable in current configurations.
+    pub fn unusable_rufs(&self, rufs: &UsedRufs) -> Vec<String> {
+        rufs.iter()
+            .filter(|ruf| !self.ruf_usable(ruf))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether a ruf is usable with all rustc versions audited against.
+    fn ruf_usable(&self, ruf: &str) -> bool {
+        self.get_rust_versions()
+            .iter()
+            .all(|rust_version| self.status_usable(&get_ruf_status(ruf, *rust_version)))
+    }
+
+    /// Whether a ruf status is usable, in stable only mode only stabilized rufs are usable.
+    pub fn status_usable(&self, status: &RufStatus) -> bool {
+        if self.stable_only {
+            status.is_stable()
+        } else {
+            status.is_usable()
+        }
+    }
+
+    /// Grade of a ruf status, unusable ones are always errors.
+    pub fn grade_status(&self, status: &RufStatus) -> Grade {
+        if !self.status_usable(status) {
+            return Grade::Error;
+        }
+
+        match status {
+            RufStatus::Active => self.active_grade,
+            RufStatus::Incomplete => self.incomplete_grade,
+            RufStatus::Accepted => self.accepted_grade,
+            RufStatus::Unknown | RufStatus::Removed => Grade::Error,
+        }
+    }
+
+    /// The worst grade of a ruf among rustc versions audited against, with its status and rustc version.
+    pub fn grade_ruf(&self, ruf: &str) -> (Grade, RufStatus, u32) {
+        self.get_rust_versions()
+            .iter()
+            .map(|rust_version| {
+                let status = get_ruf_status(ruf, *rust_version);
+                (self.grade_status(&status), status, *rust_version)
+            })
+            .max_by_key(|(grade, _, _)| *grade)
+            .expect("Fatal, no rust version audited against")
+    }
+
+    /// Rank candidate versions with candidate policy, the preferred one comes first.
+    pub fn rank_candidates(&self, cur_ver: &Version, candidates: &mut [Version]) {
+        candidates.sort();
+        // Position in the sorted candidates is used as the distance to current version,
+        // since semver versions have no numeric distance.
+        let split = candidates.partition_point(|ver| ver < cur_ver);
+        let distance = |i: usize| if i < split { split - i } else { i + 1 - split };
+
+        let mut ranked: Vec<(usize, Version)> = candidates.iter().cloned().enumerate().collect();
+        ranked.sort_by_key(|(i, ver)| {
+            let upgrade = ver > cur_ver;
+            match self.candidate_policy {
+                CandidatePolicy::PreferDowngrade => (upgrade, distance(*i)),
+                CandidatePolicy::PreferUpgrade => (!upgrade, distance(*i)),
+                CandidatePolicy::Closest => (false, distance(*i) * 2 + upgrade as usize),
+            }
+        });
+
+        for (slot, (_, ver)) in candidates.iter_mut().zip(ranked) {
+            *slot = ver;
+        }
+    }
+
+    /// Choose the preferred version among usable candidates.
+    pub fn choose_candidate(
+        &self,
+        cur_ver: &Version,
+        mut candidates: Vec<Version>,
+    ) -> Option<Version> {
+        self.rank_candidates(cur_ver, &mut candidates);
+        candidates.into_iter().next()
     }
 
     /// Get usable rustc versions for given rufs.
     pub fn usable_rustc_for_rufs(&self, rufs: &UsedRufs) -> HashSet<u32> {
         let mut usable_rustc = HashSet::from_iter(0..RUSTC_VER_NUM as u32);
         for ruf in rufs.iter() {
-            let ur = get_ruf_all_status(ruf)
-                .into_iter()
-                .enumerate()
-                .filter(|(_, status)| status.is_usable())
-                .map(|(ver, _)| ver as u32)
-                .collect();
+            let ur = self.usable_rustc_for_ruf(ruf);
             usable_rustc = usable_rustc.intersection(&ur).cloned().collect();
         }
 
         usable_rustc
     }
 
+    pub fn usable_rustc_for_ruf(&self, ruf: &str) -> HashSet<u32> {
+        get_ruf_all_status(ruf)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, status)| self.status_usable(status))
+            .map(|(ver, _)| ver as u32)
+            .collect()
+    }
+
     pub fn get_rustlib_path(&self) -> String {
         format!(
             "{rustup_home}/toolchains/nightly-2023-12-12-{host}/lib/rustlib/{host}/lib",
@@ -232,6 +509,20 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
         )
     }
 
+    /// Whether a toolchain, e.g. `nightly-2022-05-19` or `1.63`, is installed by rustup.
+    pub fn is_toolchain_installed(&self, toolchain: &str) -> bool {
+        let Ok(entries) = std::fs::read_dir(format!("{}/toolchains", self.rustup_home)) else {
+            return false;
+        };
+
+        // Stable ones may be installed with full version, e.g. `1.63.0`.
+        entries.filter_map(|entry| entry.ok()).any(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name == format!("{toolchain}-{}", self.host)
+                || (name.starts_with(&format!("{toolchain}.")) && name.ends_with(&self.host))
+        })
+    }
+
     pub fn get_cargo_args(&'long self) -> Option<&'short [String]> {
         self.cargo_args
     }
@@ -246,6 +537,213 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
         self.quick_fix
     }
 
+    #[inline]
+    pub fn set_solver_fix(&mut self, solver_fix: bool) {
+        self.solver_fix = solver_fix
+    }
+
+    #[inline]
+    pub fn is_solver_fix(&self) -> bool {
+        self.solver_fix
+    }
+
+    #[inline]
+    pub fn set_hybrid_fix(&mut self, hybrid_fix: bool) {
+        self.hybrid_fix = hybrid_fix
+    }
+
+    #[inline]
+    pub fn is_hybrid_fix(&self) -> bool {
+        self.hybrid_fix
+    }
+
+    #[inline]
+    pub fn set_stable_only(&mut self, stable_only: bool) {
+        self.stable_only = stable_only
+    }
+
+    #[inline]
+    pub fn is_stable_only(&self) -> bool {
+        self.stable_only
+    }
+
+    #[inline]
+    pub fn set_candidate_policy(&mut self, candidate_policy: CandidatePolicy) {
+        self.candidate_policy = candidate_policy
+    }
+
+    #[inline]
+    pub fn set_up_fix_depth(&mut self, up_fix_depth: usize) {
+        self.up_fix_depth = up_fix_depth
+    }
+
+    #[inline]
+    pub fn get_up_fix_depth(&self) -> usize {
+        self.up_fix_depth
+    }
+
+    #[inline]
+    pub fn set_up_fix_budget(&mut self, up_fix_budget: u64) {
+        self.up_fix_budget = up_fix_budget
+    }
+
+    #[inline]
+    pub fn get_up_fix_budget(&self) -> u64 {
+        self.up_fix_budget
+    }
+
+    #[inline]
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run
+    }
+
+    #[inline]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    #[inline]
+    pub fn set_write_toolchain(&mut self, write_toolchain: bool) {
+        self.write_toolchain = write_toolchain
+    }
+
+    #[inline]
+    pub fn is_write_toolchain(&self) -> bool {
+        self.write_toolchain
+    }
+
+    #[inline]
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify
+    }
+
+    #[inline]
+    pub fn is_verify(&self) -> bool {
+        self.verify
+    }
+
+    #[inline]
+    pub fn set_feature_fix(&mut self, feature_fix: bool) {
+        self.feature_fix = feature_fix
+    }
+
+    #[inline]
+    pub fn is_feature_fix(&self) -> bool {
+        self.feature_fix
+    }
+
+    #[inline]
+    pub fn set_relax_reqs(&mut self, relax_reqs: bool) {
+        self.relax_reqs = relax_reqs
+    }
+
+    #[inline]
+    pub fn is_relax_reqs(&self) -> bool {
+        self.relax_reqs
+    }
+
+    #[inline]
+    pub fn set_assume_yes(&mut self, assume_yes: bool) {
+        self.assume_yes = assume_yes
+    }
+
+    #[inline]
+    pub fn is_assume_yes(&self) -> bool {
+        self.assume_yes
+    }
+
+    #[inline]
+    pub fn add_patch(&mut self, crate_name: String, path: String) {
+        self.patches.insert(crate_name, path);
+    }
+
+    #[inline]
+    pub fn get_patch(&self, crate_name: &str) -> Option<&str> {
+        self.patches.get(crate_name).map(|path| path.as_str())
+    }
+
+    #[inline]
+    pub fn set_vendor_dir(&mut self, vendor_dir: String) {
+        self.vendor_dir = Some(vendor_dir)
+    }
+
+    #[inline]
+    pub fn get_vendor_dir(&self) -> Option<&str> {
+        self.vendor_dir.as_deref()
+    }
+
+    #[inline]
+    pub fn set_interactive(&mut self, interactive: bool) {
+        self.interactive = interactive
+    }
+
+    #[inline]
+    pub fn is_interactive(&self) -> bool {
+        self.interactive
+    }
+
+    #[inline]
+    pub fn set_choices_file(&mut self, choices_file: String) {
+        self.choices_file = choices_file
+    }
+
+    #[inline]
+    pub fn get_choices_file(&self) -> &str {
+        &self.choices_file
+    }
+
+    #[inline]
+    pub fn set_replay_file(&mut self, replay_file: String) {
+        self.replay_file = Some(replay_file)
+    }
+
+    #[inline]
+    pub fn get_replay_file(&self) -> Option<&str> {
+        self.replay_file.as_deref()
+    }
+
+    /// Set the grade of a usable ruf status, returns false if the status cannot be graded.
+    pub fn set_status_grade(&mut self, status: &str, grade: Grade) -> bool {
+        match status {
+            "active" => self.active_grade = grade,
+            "incomplete" => self.incomplete_grade = grade,
+            "accepted" => self.accepted_grade = grade,
+            _ => return false,
+        }
+
+        true
+    }
+
+    #[inline]
+    pub fn set_fail_grade(&mut self, fail_grade: Grade) {
+        self.fail_grade = fail_grade
+    }
+
+    #[inline]
+    pub fn get_fail_grade(&self) -> Grade {
+        self.fail_grade
+    }
+
+    #[inline]
+    pub fn set_cache(&mut self, cache: bool) {
+        self.cache = cache
+    }
+
+    #[inline]
+    pub fn is_cache(&self) -> bool {
+        self.cache
+    }
+
+    #[inline]
+    pub fn set_clear_cache(&mut self, clear_cache: bool) {
+        self.clear_cache = clear_cache
+    }
+
+    #[inline]
+    pub fn is_clear_cache(&self) -> bool {
+        self.clear_cache
+    }
+
     #[inline]
     pub fn set_verbose(&mut self, verbose: bool) {
         self.verbose = verbose
@@ -262,6 +760,28 @@ impl<'short, 'long: 'short> BuildConfig<'long> {
     }
 }
 
+impl Grade {
+    pub fn parse(grade: &str) -> Option<Self> {
+        match grade {
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let grade = match self {
+            Grade::Info => "info",
+            Grade::Warning => "warning",
+            Grade::Error => "error",
+        };
+        write!(f, "{grade}")
+    }
+}
+
 // #[test]
 // fn test() {
 //     let config = BuildConfig::default();
@@ -4,6 +4,25 @@ use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet}
 
 mod r#impl;
 
+/// How candidate versions of a crate are ranked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidatePolicy {
+    /// Newer versions first, then older ones, the nearest first.
+    PreferUpgrade,
+    /// Older versions first, then newer ones, the nearest first.
+    PreferDowngrade,
+    /// Versions nearest to current one first, regardless of directions.
+    Closest,
+}
+
+/// How serious a ruf finding is, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Grade {
+    Info,
+    Warning,
+    Error,
+}
+
 #[derive(Debug)]
 pub struct BuildConfig<'c> {
     // host triple
@@ -15,6 +34,12 @@ pub struct BuildConfig<'c> {
     cargo_home: String,
     // current configured rust version
     rust_version: u32,
+    // rust version of default toolchain, if newer than our lifetime table (default none)
+    unsupported_rustc: Option<u32>,
+    // rust versions every crate must be usable with, in multi-toolchain mode (default none)
+    rust_versions: Vec<u32>,
+    // only stabilized rufs are usable, as on stable rustc (default true only on stable toolchain)
+    stable_only: bool,
     // cargo configurations during building
     cargo_args: Option<&'c [String]>,
     // dependency configurations during building
@@ -22,6 +47,50 @@ pub struct BuildConfig<'c> {
 
     // fix with rustc and minimal dep tree, which is the quickest way (default false)
     quick_fix: bool,
+    // fix dep tree with global constraint solving, rather than fixing crates one by one (default false)
+    solver_fix: bool,
+    // fix with the rustc needing fewest dependency changes, ranked with alternatives (default false)
+    hybrid_fix: bool,
+    // which candidate version to choose when fixing a crate (default prefer downgrade)
+    candidate_policy: CandidatePolicy,
+    // max layers of parents and changes in a row tried by up fix (default 10)
+    up_fix_depth: usize,
+    // time budget of each up fix search or solver run, in seconds (default 300)
+    up_fix_budget: u64,
+    // only show the fix plan, and leave `Cargo.lock` untouched (default false)
+    dry_run: bool,
+    // write the rustc chosen by rustc fix into `rust-toolchain.toml` (default false)
+    write_toolchain: bool,
+    // build check the fixed lockfile with installed candidate rustc (default false)
+    verify: bool,
+    // turn off features enabling unusable rufs in local manifests (default false)
+    feature_fix: bool,
+    // relax requirements in local manifests blocking usable versions (default false)
+    relax_reqs: bool,
+    // apply changes to manifests without asking (default false)
+    assume_yes: bool,
+    // local paths patching crates, as `[patch.crates-io]` (default none)
+    patches: HashMap<String, String>,
+    // directory to vendor usable versions into, for patching (default none)
+    vendor_dir: Option<String>,
+    // ask user to approve each down fix step (default false)
+    interactive: bool,
+    // where decisions made in interactive mode are saved (default `ruf_audit_choices.json`)
+    choices_file: String,
+    // decisions to replay, saved by interactive mode before (default none)
+    replay_file: Option<String>,
+    // grade of active rufs (default info)
+    active_grade: Grade,
+    // grade of incomplete rufs, which are known to be unsound (default warning)
+    incomplete_grade: Grade,
+    // grade of accepted rufs, whose `#![feature]` is no longer needed (default warning)
+    accepted_grade: Grade,
+    // audit fails if any ruf is graded at least this (default error)
+    fail_grade: Grade,
+    // cache database, index and filtered results on disk (default true)
+    cache: bool,
+    // clear the cache before audit (default false)
+    clear_cache: bool,
     // print check details
     verbose: bool,
     // test mode, not provided to user
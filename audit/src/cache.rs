@@ -0,0 +1,243 @@
+//! This file caches results across runs on disk, under cargo home:
+//! rufs recorded in database, rufs filtered under cfgs, and dependency requirements in index.
+//!
+//! Results from database are keyed by the database snapshot, so that they are dropped once database changes.
+//! Requirements of a published version never change, thus are kept for good.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use basic_usages::external::fxhash::{self, FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+use basic_usages::external::serde::de::DeserializeOwned;
+use basic_usages::external::serde::Serialize;
+use basic_usages::external::serde_json;
+use basic_usages::ruf_check_info::{CondRufs, UsedRufs};
+use basic_usages::ruf_db_usage;
+
+use cargo_metadata::semver::VersionReq;
+use lazy_static::lazy_static;
+
+use crate::error::AuditError;
+
+lazy_static! {
+    static ref CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+}
+
+/// Cached entries of a kind, one file per crate.
+struct Table<V> {
+    dir: &'static str,
+    /// loaded crates, `None` if no file found.
+    crates: HashMap<String, Option<HashMap<String, V>>>,
+    dirty: HashSet<String>,
+}
+
+struct Cache {
+    root: PathBuf,
+    /// `None` if not queried yet, `Some(None)` if database is not available.
+    db_snapshot: Option<Option<String>>,
+
+    rufs: Table<CondRufs>,
+    filtered: Table<UsedRufs>,
+    reqs: Table<Vec<(String, String)>>,
+}
+
+impl<V: Serialize + DeserializeOwned + Clone> Table<V> {
+    fn new(dir: &'static str) -> Self {
+        Self {
+            dir,
+            crates: HashMap::default(),
+            dirty: HashSet::default(),
+        }
+    }
+
+    fn load(&mut self, base: &Path, name: &str) -> &mut Option<HashMap<String, V>> {
+        let path = base.join(self.dir).join(format!("{name}.json"));
+        self.crates.entry(name.to_string()).or_insert_with(|| {
+            // Broken files are simply ignored, and overwritten later.
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|content| serde_json::from_str(&content).ok())
+        })
+    }
+
+    fn get(&mut self, base: &Path, name: &str, key: &str) -> Option<V> {
+        self.load(base, name).as_ref()?.get(key).cloned()
+    }
+
+    fn get_all(&mut self, base: &Path, name: &str) -> Option<HashMap<String, V>> {
+        self.load(base, name).clone()
+    }
+
+    fn insert(&mut self, base: &Path, name: &str, key: String, value: V) {
+        self.load(base, name)
+            .get_or_insert_with(HashMap::default)
+            .insert(key, value);
+        self.dirty.insert(name.to_string());
+    }
+
+    fn insert_all(&mut self, name: &str, entries: HashMap<String, V>) {
+        self.crates.insert(name.to_string(), Some(entries));
+        self.dirty.insert(name.to_string());
+    }
+
+    fn flush(&mut self, base: &Path) -> Result<(), AuditError> {
+        let dir = base.join(self.dir);
+        for name in self.dirty.drain() {
+            let Some(Some(entries)) = self.crates.get(&name) else {
+                continue;
+            };
+            fs::create_dir_all(&dir).map_err(|e| {
+                AuditError::Unexpected(format!("cannot create `{}`: {e}", dir.display()))
+            })?;
+            let path = dir.join(format!("{name}.json"));
+            let content = serde_json::to_string(entries).expect("Fatal, cannot serialize cache");
+            fs::write(&path, content).map_err(|e| {
+                AuditError::Unexpected(format!("cannot write `{}`: {e}", path.display()))
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Cache {
+    /// Where results from database are cached, `None` if database is not available.
+    fn db_dir(&mut self) -> Option<PathBuf> {
+        let snapshot = self
+            .db_snapshot
+            .get_or_insert_with(|| ruf_db_usage::get_db_snapshot().ok());
+
+        snapshot
+            .as_ref()
+            .map(|snapshot| self.root.join(format!("db-{snapshot}")))
+    }
+}
+
+/// The cache directory, under cargo home.
+pub fn cache_dir() -> PathBuf {
+    let cargo_home = std::env::var("CARGO_HOME").unwrap_or_else(|_| {
+        format!(
+            "{}/.cargo",
+            std::env::var("HOME").expect("Fatal, cannot find home directory")
+        )
+    });
+
+    Path::new(&cargo_home).join("ruf_audit_cache")
+}
+
+/// Enable the cache, and clear it first if asked.
+pub fn init(enabled: bool, clear: bool) -> Result<(), AuditError> {
+    let root = cache_dir();
+    if clear {
+        match fs::remove_dir_all(&root) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(AuditError::Unexpected(format!(
+                    "cannot clear cache `{}`: {e}",
+                    root.display()
+                )))
+            }
+            _ => {}
+        }
+    }
+
+    *CACHE.lock().unwrap() = enabled.then(|| Cache {
+        root,
+        db_snapshot: None,
+        rufs: Table::new("rufs"),
+        filtered: Table::new("filtered"),
+        reqs: Table::new("reqs"),
+    });
+
+    Ok(())
+}
+
+/// Write new results to disk.
+pub fn flush() -> Result<(), AuditError> {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut() else {
+        return Ok(());
+    };
+
+    cache.reqs.flush(&cache.root)?;
+    if let Some(db_dir) = cache.db_dir() {
+        cache.rufs.flush(&db_dir)?;
+        cache.filtered.flush(&db_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Same as [`ruf_db_usage::get_rufs_with_crate_name`], but cached.
+pub fn get_rufs_with_crate_name(name: &str) -> Result<HashMap<Version, CondRufs>, String> {
+    let mut cache = CACHE.lock().unwrap();
+    let cached = cache.as_mut().and_then(|cache| {
+        let db_dir = cache.db_dir()?;
+        cache.rufs.get_all(&db_dir, name)
+    });
+    if let Some(cached) = cached {
+        return Ok(cached
+            .into_iter()
+            .filter_map(|(ver, rufs)| Some((Version::parse(&ver).ok()?, rufs)))
+            .collect());
+    }
+
+    let rufs = ruf_db_usage::get_rufs_with_crate_name(name)?;
+    if let Some(cache) = cache.as_mut() {
+        let entries = rufs
+            .iter()
+            .map(|(ver, rufs)| (ver.to_string(), rufs.clone()))
+            .collect();
+        cache.rufs.insert_all(name, entries);
+    }
+
+    Ok(rufs)
+}
+
+/// Key of filtered rufs, by version and cfgs.
+pub fn filtered_key(ver: &Version, cfgs: &[String]) -> String {
+    let mut cfgs = cfgs.to_vec();
+    cfgs.sort();
+    format!("{ver} {:016x}", fxhash::hash64(&cfgs))
+}
+
+pub fn get_filtered(name: &str, key: &str) -> Option<UsedRufs> {
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.as_mut()?;
+    let db_dir = cache.db_dir()?;
+    cache.filtered.get(&db_dir, name, key)
+}
+
+pub fn put_filtered(name: &str, key: String, used_rufs: UsedRufs) {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut() else {
+        return;
+    };
+    if let Some(db_dir) = cache.db_dir() {
+        cache.filtered.insert(&db_dir, name, key, used_rufs);
+    }
+}
+
+pub fn get_reqs(name: &str, ver: &str) -> Option<Vec<(String, VersionReq)>> {
+    let mut cache = CACHE.lock().unwrap();
+    let cache = cache.as_mut()?;
+    let reqs = cache.reqs.get(&cache.root, name, ver)?;
+
+    reqs.into_iter()
+        .map(|(dep, req)| Some((dep, VersionReq::parse(&req).ok()?)))
+        .collect()
+}
+
+pub fn put_reqs(name: &str, ver: &str, reqs: &[(String, VersionReq)]) {
+    let mut cache = CACHE.lock().unwrap();
+    let Some(cache) = cache.as_mut() else {
+        return;
+    };
+
+    let reqs = reqs
+        .iter()
+        .map(|(dep, req)| (dep.clone(), req.to_string()))
+        .collect();
+    cache.reqs.insert(&cache.root, name, ver.to_string(), reqs);
+}
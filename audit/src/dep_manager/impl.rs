@@ -1,27 +1,37 @@
 use std::cell::RefCell;
+use std::collections::VecDeque;
 
-use basic_usages::external::fxhash::FxHashMap as HashMap;
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use basic_usages::external::semver::Version;
 use basic_usages::ruf_check_info::CondRufs;
 
 use cargo_lock::dependency::graph::{EdgeDirection, Graph, NodeIndex};
 use cargo_lock::Lockfile;
 use cargo_metadata::semver::VersionReq;
-use cargo_metadata::MetadataCommand;
+use cargo_metadata::{Metadata, MetadataCommand};
 use petgraph::visit::EdgeRef;
 use tame_index::external::reqwest;
 use tame_index::utils::flock::LockOptions;
 use tame_index::{IndexLocation, KrateName, SparseIndex};
 // use tame_index::index::FileLock;
 
-use crate::error::AuditError;
+use crate::cache;
+use crate::error::{AuditError, CandidateError};
+use crate::fix_plan::FixStep;
+use crate::report::Rejection;
+use crate::transaction;
 use crate::{spec_cargo, RUSTV};
 
-use super::DepManager;
+use super::{DepManager, LockSnapshot};
 
 impl DepManager<'_> {
     /// Create a new DepManager from current configurations.
     pub fn new() -> Result<Self, AuditError> {
+        // Metadata goes first, it also updates `Cargo.lock` if manifests are changed.
+        let metadata = load_metadata()?;
+        let resolved_features = resolved_features(&metadata);
+        let workspace_root = metadata.workspace_root.to_string();
+
         let lockfile = Lockfile::load("Cargo.lock").map_err(|e| {
             AuditError::Unexpected(format!(
                 "cannot build DepManager, load lock file fails: {e}",
@@ -32,20 +42,13 @@ impl DepManager<'_> {
             AuditError::Unexpected(format!("cannot build DepManager, load dep tree fails: {e}",))
         })?;
 
-        let metadata = MetadataCommand::new()
-            .env("RUSTUP_TOOLCHAIN", RUSTV)
-            .exec()
-            .map_err(|e| {
-                AuditError::Unexpected(
-                    format!("cannot build DepManager, load metadata fails: {e}",),
-                )
-            })?;
-
         let mut local_crates = HashMap::default();
+        let mut local_manifests = HashMap::default();
         for pkg in metadata.packages {
             if pkg.source.is_none() {
                 // no source means local
                 let name_ver = format!("{}@{}", pkg.name, pkg.version);
+                local_manifests.insert(name_ver.clone(), pkg.manifest_path.to_string());
                 let deps = pkg
                     .dependencies
                     .into_iter()
@@ -73,12 +76,18 @@ impl DepManager<'_> {
         })?;
 
         let req_by = RefCell::new(HashMap::default());
+        let rejected = RefCell::new(HashMap::default());
         Ok(Self {
             index,
             lock,
             dep_tree,
             req_by,
+            rejected,
             local_crates,
+            local_manifests,
+            workspace_root,
+            resolved_features,
+            fix_steps: vec![],
         })
     }
 
@@ -97,11 +106,18 @@ impl DepManager<'_> {
         }
 
         let parents = self.get_dep_parent(pkgnx);
-        assert!(parents.len() >= 1, "Fatal, root has no parents");
+        if parents.is_empty() {
+            return Err(CandidateError::NoParent { name: name_ver }.into());
+        }
 
-        let candidates = basic_usages::ruf_db_usage::get_rufs_with_crate_name(pkg.name.as_str())
-            .map_err(|e| AuditError::Unexpected(format!("cannot get candidates, due to {e}")))?;
+        let candidates = cache::get_rufs_with_crate_name(pkg.name.as_str()).map_err(|reason| {
+            CandidateError::NotInDb {
+                name: pkg.name.to_string(),
+                reason,
+            }
+        })?;
 
+        self.rejected.borrow_mut().insert(pkgnx, vec![]);
         // Early return.
         if candidates.is_empty() {
             return Ok(candidates);
@@ -120,7 +136,10 @@ impl DepManager<'_> {
             let req = meta
                 .into_iter()
                 .find(|(name, _)| name == pkg.name.as_str())
-                .expect("Fatal, cannot find dependency in parent package")
+                .ok_or_else(|| CandidateError::DepNotFound {
+                    parent: format!("{}@{}", p_pkg.name, p_pkg.version),
+                    dep: pkg.name.to_string(),
+                })?
                 .1;
             // prepare for relaxing strict parents.
             let lowest = candidates
@@ -128,35 +147,53 @@ impl DepManager<'_> {
                 .filter(|key| req.matches(key))
                 .min()
                 .cloned()
-                .expect("Fatal, cannot find lowest allowing version");
+                .ok_or_else(|| CandidateError::NoMatchingVersion {
+                    parent: format!("{}@{}", p_pkg.name, p_pkg.version),
+                    dep: pkg.name.to_string(),
+                    req: req.clone(),
+                })?;
             version_reqs.push((p, req, lowest));
         }
 
+        // Versions not allowed by parents, recorded for reporting.
+        let mut rejected = vec![];
+        for ver in candidates.keys().filter(|ver| *ver != &pkg.version) {
+            if let Some((p, req, _)) = version_reqs.iter().find(|(_, req, _)| !req.matches(ver)) {
+                let p_pkg = &self.graph()[*p];
+                rejected.push((
+                    ver.clone(),
+                    Rejection::Semver(format!(
+                        "`{req}` required by {}@{}",
+                        p_pkg.name, p_pkg.version
+                    )),
+                ));
+            }
+        }
+        self.rejected.borrow_mut().insert(pkgnx, rejected);
+
         // We assume parents who restricts the version most is the one not allow min_lowest,
         // and it shall be updated later, if we need up fix.
         // This assumption won't hold for all cases (cases with complex version req),
         // but most of the times it works.
+        //
+        // If all parents allow the same lowest version, e.g. only one parent, any of them can be the one.
         let min_lowest = version_reqs
             .iter()
             .map(|vr| &vr.2)
             .min()
             .expect("Fatal, no min version found");
 
-        let mut req_by = None;
-        for version_req in version_reqs.iter() {
-            if version_req.2 > *min_lowest {
-                req_by = Some(version_req.0);
-                break;
-            }
-        }
+        let req_by = version_reqs
+            .iter()
+            .find(|version_req| version_req.2 > *min_lowest)
+            .unwrap_or(&version_reqs[0])
+            .0;
 
-        self.req_by
-            .borrow_mut()
-            .insert(pkgnx, req_by.expect("Fatal, no strict parent found"));
+        self.req_by.borrow_mut().insert(pkgnx, req_by);
 
         // we choose candidates as:
         // 1. match its dependents' version req
-        // 2. smaller than current version
+        // 2. differ from current version, either older or newer
         // we will record who restricts the version most, for later up fix.
         //
         // The ruf usability check will be done later, differ from design.
@@ -165,46 +202,43 @@ impl DepManager<'_> {
             .filter(|(ver, _)| {
                 version_reqs
                     .iter()
-                    .all(|(_, req, _)| req.matches(ver) && ver < &pkg.version)
+                    .all(|(_, req, _)| req.matches(ver) && ver != &pkg.version)
             })
             .collect();
 
         Ok(candidates)
     }
 
-    /// Used in up fix, same as [`get_candidates`], but get candidates for the dependent, which has older version req
-    /// to the dep package.
+    /// Used in up fix, same as [`get_candidates`], but get candidates for the dependent, whose version req
+    /// to the dep package admits at least one of `usable_dep_vers`.
     pub fn get_candidates_up_fix(
         &self,
         pkgnx: NodeIndex,
         dep_pkgnx: NodeIndex,
+        usable_dep_vers: &[Version],
     ) -> Result<HashMap<Version, CondRufs>, AuditError> {
         let pkg = &self.graph()[pkgnx];
         let dep_pkg = &self.graph()[dep_pkgnx];
 
         let pkg_name = pkg.name.as_str();
-        let pkg_ver = pkg.version.to_string();
-
         let dep_name = dep_pkg.name.as_str();
         let candidates = self.get_candidates(pkgnx)?;
 
-        // We find out version with looser req to dep_pkgnx
+        // We find out version whose req to dep_pkgnx allows usable versions.
         let mut res = HashMap::default();
-        let cur_req = self
-            .get_package_reqs(pkg_name, pkg_ver.as_str())?
-            .into_iter()
-            .find(|(name, _)| name == dep_name)
-            .expect("Fatal, cannot find dependency in current parent package")
-            .1;
-
         for cad in candidates {
             let reqs = self.get_package_reqs(pkg_name, cad.0.to_string().as_str())?;
 
             if let Some((_, req)) = reqs.into_iter().find(|(name, _)| name == dep_name) {
-                // We take the assumption that, older verison shall have looser semver req, 
-                // so if req differs, we assume it's a candidate, since semver comparision can be hard.
-                if req != cur_req {
+                if usable_dep_vers.iter().any(|ver| req.matches(ver)) {
                     res.insert(cad.0, cad.1);
+                } else {
+                    self.rejected.borrow_mut().entry(pkgnx).or_default().push((
+                        cad.0,
+                        Rejection::Semver(format!(
+                            "`{req}` on {dep_name} admits no usable version"
+                        )),
+                    ));
                 }
             } else {
                 // dep not found, possibily not used, thus ok.
@@ -215,6 +249,18 @@ impl DepManager<'_> {
         Ok(res)
     }
 
+    /// All versions of a package recorded in database, regardless of its parents' version req.
+    pub fn get_versions(&self, pkgnx: NodeIndex) -> Result<HashMap<Version, CondRufs>, AuditError> {
+        let name = self.graph()[pkgnx].name.as_str();
+        cache::get_rufs_with_crate_name(name).map_err(|reason| {
+            CandidateError::NotInDb {
+                name: name.to_string(),
+                reason,
+            }
+            .into()
+        })
+    }
+
     /// Update package version in Cargo.lock, using cargo update subcommand.
     pub fn update_pkg(
         &mut self,
@@ -227,20 +273,194 @@ impl DepManager<'_> {
         let mut cargo = spec_cargo(RUSTV);
         cargo.args(["update", &name_ver, "--precise", update_ver]);
 
-        let output = cargo.output().expect("Fatal, execute cargo update fails");
+        let output = cargo
+            .output()
+            .map_err(|e| AuditError::Unexpected(format!("cannot run cargo update: {e}")))?;
         if !output.status.success() {
-            return Err(AuditError::Unexpected(format!(
-                "cannot update dep version, execute cargo update fails: {}",
-                String::from_utf8_lossy(&output.stderr)
-            )));
+            return Err(CandidateError::UpdateFailed {
+                name: name_ver,
+                ver: update_ver.to_string(),
+                reason: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            }
+            .into());
         }
 
         self.update_dep_tree()?;
         self.req_by.borrow_mut().clear();
+        self.rejected.borrow_mut().clear();
+        self.fix_steps.push(FixStep::Update {
+            name: name.to_string(),
+            from: cur_ver.to_string(),
+            to: update_ver.to_string(),
+        });
 
         Ok(())
     }
 
+    /// Minimize subgraphs of issued crates, given by crate names, i.e. the crates and their dependencies
+    /// are changed to the oldest versions admitted by their dependents. Other packages are kept as locked.
+    ///
+    /// Returns packages failed to be minimized, as `name@version`.
+    pub fn minimal_subtrees(
+        &mut self,
+        issued: &HashSet<String>,
+    ) -> Result<Vec<String>, AuditError> {
+        let mut tried = HashSet::default();
+        let mut failed = vec![];
+        // Changing a package reshapes the subgraphs below it, so we walk again after each change.
+        while let Some((name_ver, oldest)) = self.next_minimal(issued, &mut tried)? {
+            let (name, cur_ver) = name_ver.rsplit_once('@').expect("Fatal, invalid name_ver");
+            // Cargo may refuse the change due to other requirements, then the package is kept.
+            if self.update_pkg(name, cur_ver, &oldest.to_string()).is_err() {
+                failed.push(name_ver);
+            }
+        }
+
+        Ok(failed)
+    }
+
+    /// The next package in subgraphs of issued crates which can be older, parents first.
+    fn next_minimal(
+        &self,
+        issued: &HashSet<String>,
+        tried: &mut HashSet<String>,
+    ) -> Result<Option<(String, Version)>, AuditError> {
+        let graph = self.graph();
+        let mut queue: VecDeque<NodeIndex> = graph
+            .node_indices()
+            .filter(|nx| issued.contains(&graph[*nx].name.as_str().replace('-', "_")))
+            .collect();
+        let mut visited: HashSet<NodeIndex> = queue.iter().cloned().collect();
+
+        while let Some(nx) = queue.pop_front() {
+            for dep in graph.neighbors_directed(nx, EdgeDirection::Outgoing) {
+                if visited.insert(dep) {
+                    queue.push_back(dep);
+                }
+            }
+
+            let pkg = &graph[nx];
+            let name_ver = format!("{}@{}", pkg.name, pkg.version);
+            if !tried.insert(name_ver.clone()) {
+                continue;
+            }
+
+            let oldest = match self.get_candidates(nx) {
+                Ok(candidates) => candidates.into_keys().min(),
+                Err(AuditError::Candidate(_)) => None,
+                Err(e) => return Err(e),
+            };
+            if let Some(oldest) = oldest.filter(|oldest| *oldest < pkg.version) {
+                return Ok(Some((name_ver, oldest)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Snapshot current lockfile, manifests tracked by the transaction, and changes made so far.
+    pub fn snapshot(&self) -> Result<LockSnapshot, AuditError> {
+        let lock = std::fs::read_to_string("Cargo.lock")
+            .map_err(|e| AuditError::Unexpected(format!("cannot snapshot lock file: {e}")))?;
+
+        Ok(LockSnapshot {
+            lock,
+            files: transaction::snapshot_tracked()?,
+            steps: self.fix_steps.len(),
+        })
+    }
+
+    /// Restore the lockfile, manifests and changes to a snapshot.
+    pub fn restore(&mut self, snapshot: &LockSnapshot) -> Result<(), AuditError> {
+        let changed = transaction::restore_tracked(&snapshot.files)?;
+        std::fs::write("Cargo.lock", &snapshot.lock)
+            .map_err(|e| AuditError::Unexpected(format!("cannot restore lock file: {e}")))?;
+
+        if changed.iter().any(|path| path != "Cargo.lock") {
+            // Manifests edited since the snapshot, local crates may change too.
+            self.reload()?;
+        } else {
+            self.update_dep_tree()?;
+        }
+        self.req_by.borrow_mut().clear();
+        self.rejected.borrow_mut().clear();
+        self.fix_steps.truncate(snapshot.steps);
+
+        Ok(())
+    }
+
+    /// Reload the dependency tree and local crates, after manifests are changed.
+    pub fn reload(&mut self) -> Result<(), AuditError> {
+        let fix_steps = std::mem::take(&mut self.fix_steps);
+        let resolved_features = std::mem::take(&mut self.resolved_features);
+        *self = Self::new()?;
+        self.fix_steps = fix_steps;
+        // Kept, so that changes made by manifest edits are still found by `changed_crates`.
+        self.resolved_features = resolved_features;
+
+        Ok(())
+    }
+
+    /// Record a change made outside, e.g. manifest edits.
+    pub fn push_step(&mut self, step: FixStep) {
+        self.fix_steps.push(step);
+    }
+
+    /// Changes made to the dependency tree so far.
+    pub fn fix_steps(&self) -> &[FixStep] {
+        &self.fix_steps
+    }
+
+    /// Update several packages at once, e.g. a solution from [`crate::solver::Solver`].
+    ///
+    /// Packages are given as `(name, current version, new version)`, parents first.
+    /// An update may fail until its dependents are updated, so we retry until no progress is made.
+    pub fn update_pkgs(&mut self, updates: &[(String, String, String)]) -> Result<(), AuditError> {
+        let mut pending = updates.to_vec();
+        loop {
+            let pending_num = pending.len();
+            let mut failed = vec![];
+            let mut last_err = None;
+            for (name, cur_ver, update_ver) in pending {
+                let exists = |ver: &str| {
+                    self.graph()
+                        .node_weights()
+                        .any(|pkg| pkg.name.as_str() == name && pkg.version.to_string() == ver)
+                };
+                if exists(&update_ver) || !exists(&cur_ver) {
+                    // already updated, or dropped, along with other packages.
+                    continue;
+                }
+
+                if let Err(e) = self.update_pkg(&name, &cur_ver, &update_ver) {
+                    last_err = Some(e);
+                    failed.push((name, cur_ver, update_ver));
+                }
+            }
+
+            match last_err {
+                None => return Ok(()),
+                Some(e) if failed.len() == pending_num => return Err(e),
+                Some(_) => pending = failed,
+            }
+        }
+    }
+
+    /// Packages whose compilation units changed since last call, by names,
+    /// i.e. packages newly added to the tree, e.g. from another source through `[patch]`,
+    /// and those with resolved features changed.
+    pub fn changed_crates(&mut self) -> Result<HashSet<String>, AuditError> {
+        let resolved_features = resolved_features(&load_metadata()?);
+        let changed = resolved_features
+            .iter()
+            .filter(|(id, resolved)| self.resolved_features.get(*id) != Some(resolved))
+            .map(|(_, (name, _))| name.clone())
+            .collect();
+        self.resolved_features = resolved_features;
+
+        Ok(changed)
+    }
+
     pub fn root(&self) -> NodeIndex {
         let roots = self.dep_tree.roots();
         assert!(roots.len() == 1); // When will this not be 1 ?
@@ -255,7 +475,62 @@ impl DepManager<'_> {
         self.req_by.borrow().get(dep).cloned()
     }
 
-    fn get_package_reqs(
+    /// Versions rejected when fetching candidates of `pkgnx` last time.
+    pub fn take_rejected(&self, pkgnx: &NodeIndex) -> Vec<(Version, Rejection)> {
+        self.rejected.borrow_mut().remove(pkgnx).unwrap_or_default()
+    }
+
+    pub fn is_local(&self, name: &str, ver: &str) -> bool {
+        self.local_crates.contains_key(&format!("{name}@{ver}"))
+    }
+
+    /// Whether any version of the crate is a local one, e.g. patched with a path.
+    pub fn has_local(&self, name: &str) -> bool {
+        self.local_crates
+            .keys()
+            .any(|name_ver| name_ver.rsplit_once('@').map_or(false, |(n, _)| n == name))
+    }
+
+    pub fn local_manifest(&self, name: &str, ver: &str) -> Option<&str> {
+        self.local_manifests
+            .get(&format!("{name}@{ver}"))
+            .map(|path| path.as_str())
+    }
+
+    pub fn workspace_root(&self) -> &str {
+        &self.workspace_root
+    }
+
+    /// Manifest of current workspace root.
+    pub fn root_manifest(&self) -> String {
+        format!("{}/Cargo.toml", self.workspace_root)
+    }
+
+    /// Versions of the dep admitted by all its parents, except the given one.
+    pub fn admitted_by_others(
+        &self,
+        depnx: NodeIndex,
+        except: NodeIndex,
+        vers: &[Version],
+    ) -> Result<Vec<Version>, AuditError> {
+        let dep_name = self.graph()[depnx].name.as_str();
+        let mut admitted = vers.to_vec();
+        for p in self
+            .get_dep_parent(depnx)
+            .into_iter()
+            .filter(|p| *p != except)
+        {
+            let p_pkg = &self.graph()[p];
+            let reqs = self.get_package_reqs(p_pkg.name.as_str(), &p_pkg.version.to_string())?;
+            if let Some((_, req)) = reqs.iter().find(|(name, _)| name == dep_name) {
+                admitted.retain(|ver| req.matches(ver));
+            }
+        }
+
+        Ok(admitted)
+    }
+
+    pub fn get_package_reqs(
         &self,
         name: &str,
         ver: &str,
@@ -266,34 +541,41 @@ impl DepManager<'_> {
             return Ok(self.local_crates[&name_ver].clone());
         }
 
+        if let Some(reqs) = cache::get_reqs(name, ver) {
+            return Ok(reqs);
+        }
+
         // else we fetch from remote
-        let krate: KrateName = name
-            .try_into()
-            .expect(&format!("Fatal, cannot convert {name} to KrateName"));
+        // names not valid in crates.io cannot be found in index.
+        let krate: KrateName = name.try_into().map_err(|_| CandidateError::NotInIndex {
+            name: name.to_string(),
+            ver: ver.to_string(),
+        })?;
 
         // search local cache first, lock crates index is needed
         let lock = self
             .lock
             .lock(|_| None)
-            .expect("Fatal, cannot get file lock");
-        let res = self.index.cached_krate(krate.clone(), &lock).map_err(|e| {
+            .map_err(|e| AuditError::Unexpected(format!("cannot lock crates index: {e}")))?;
+        let res = self.index.cached_krate(krate, &lock).map_err(|e| {
             AuditError::Unexpected(format!(
                 "cannot get package {name}-{ver} metadata from index: {e}"
             ))
         })?;
 
-        if let Some(iv) = res
-            .map(|krate| krate.versions.into_iter().find(|iv| iv.version == ver))
-            .flatten()
+        if let Some(iv) =
+            res.and_then(|krate| krate.versions.into_iter().find(|iv| iv.version == ver))
         {
-            return Ok(iv
+            let reqs: Vec<_> = iv
                 .dependencies()
-                .into_iter()
+                .iter()
                 .map(|dep| {
                     let req = dep.version_requirement();
                     (dep.crate_name().to_string(), req)
                 })
-                .collect());
+                .collect();
+            cache::put_reqs(name, ver, &reqs);
+            return Ok(reqs);
         }
 
         // Or from remote
@@ -303,23 +585,26 @@ impl DepManager<'_> {
             ))
         })?;
 
-        if let Some(iv) = res
-            .map(|krate| krate.versions.into_iter().find(|iv| iv.version == ver))
-            .flatten()
+        if let Some(iv) =
+            res.and_then(|krate| krate.versions.into_iter().find(|iv| iv.version == ver))
         {
-            return Ok(iv
+            let reqs: Vec<_> = iv
                 .dependencies()
-                .into_iter()
+                .iter()
                 .map(|dep| {
                     let req = dep.version_requirement();
                     (dep.crate_name().to_string(), req)
                 })
-                .collect());
+                .collect();
+            cache::put_reqs(name, ver, &reqs);
+            return Ok(reqs);
         }
 
-        return Err(AuditError::Unexpected(format!(
-            "cannot get package {name}-{ver} metadata from index: cannot find in index"
-        )));
+        Err(CandidateError::NotInIndex {
+            name: name.to_string(),
+            ver: ver.to_string(),
+        }
+        .into())
     }
 
     fn get_dep_parent(&self, depnx: NodeIndex) -> Vec<NodeIndex> {
@@ -344,3 +629,37 @@ impl DepManager<'_> {
         Ok(())
     }
 }
+
+fn load_metadata() -> Result<Metadata, AuditError> {
+    MetadataCommand::new()
+        .env("RUSTUP_TOOLCHAIN", RUSTV)
+        .exec()
+        .map_err(|e| AuditError::Unexpected(format!("cannot load metadata: {e}")))
+}
+
+/// Resolved features of each package, along with its name, keyed by package id.
+///
+/// Package ids tell sources apart, so a crate patched with the same version is still a different one.
+fn resolved_features(metadata: &Metadata) -> HashMap<String, (String, Vec<String>)> {
+    let Some(resolve) = &metadata.resolve else {
+        return HashMap::default();
+    };
+    let names: HashMap<_, _> = metadata
+        .packages
+        .iter()
+        .map(|pkg| (&pkg.id, pkg.name.clone()))
+        .collect();
+
+    resolve
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let mut features = node.features.clone();
+            features.sort();
+            Some((
+                node.id.repr.clone(),
+                (names.get(&node.id)?.clone(), features),
+            ))
+        })
+        .collect()
+}
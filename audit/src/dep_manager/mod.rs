@@ -3,6 +3,7 @@
 use std::cell::RefCell;
 
 use basic_usages::external::fxhash::FxHashMap as HashMap;
+use basic_usages::external::semver::Version;
 
 use cargo_lock::dependency::graph::NodeIndex;
 use cargo_lock::dependency::Tree;
@@ -10,8 +11,20 @@ use cargo_metadata::semver::VersionReq;
 use tame_index::index::RemoteSparseIndex;
 use tame_index::utils::flock::LockOptions;
 
+use crate::fix_plan::FixStep;
+use crate::report::Rejection;
+
 mod r#impl;
 
+/// Lockfile and changes made at some point, to be restored later.
+#[derive(Clone)]
+pub struct LockSnapshot {
+    lock: String,
+    /// other files tracked by the transaction, e.g. manifests.
+    files: Vec<(String, Option<String>)>,
+    steps: usize,
+}
+
 pub struct DepManager<'long> {
     // lockfile: Lockfile,
     index: RemoteSparseIndex,
@@ -21,7 +34,18 @@ pub struct DepManager<'long> {
     dep_tree: Tree,
     /// record parent with strictest semver reqs.
     req_by: RefCell<HashMap<NodeIndex, NodeIndex>>,
+    /// record versions rejected when fetching candidates, for reporting.
+    rejected: RefCell<HashMap<NodeIndex, Vec<(Version, Rejection)>>>,
 
     /// local crates and thire semver reqs on the dependencies.
     local_crates: HashMap<String, Vec<(String, VersionReq)>>,
+    /// manifest paths of local crates.
+    local_manifests: HashMap<String, String>,
+    /// root directory of current workspace, where `[patch]` goes.
+    workspace_root: String,
+    /// names and resolved features of each package by package id, as of last extraction.
+    resolved_features: HashMap<String, (String, Vec<String>)>,
+
+    /// changes made to the dependency tree, in order.
+    fix_steps: Vec<FixStep>,
 }
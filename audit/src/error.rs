@@ -2,35 +2,96 @@
 
 use std::fmt::Display;
 
+use cargo_metadata::semver::VersionReq;
+
 #[derive(Debug)]
 pub enum AuditError {
     /// Not our issues, maybe cargo or other commands fails.
     Unexpected(String),
     /// Our tool fails
     Functionality(String),
+    /// Cannot fetch candidates of a crate, the crate can be skipped.
+    Candidate(CandidateError),
+}
+
+/// Why candidates of a crate cannot be fetched, or applied.
+#[derive(Debug)]
+pub enum CandidateError {
+    /// Crate not recorded in database.
+    NotInDb { name: String, reason: String },
+    /// Package version not found in index.
+    NotInIndex { name: String, ver: String },
+    /// Dependency not found in parent's requirements, e.g. renamed or optional ones.
+    DepNotFound { parent: String, dep: String },
+    /// No version in database allowed by parent's requirement, e.g. only yanked ones are.
+    NoMatchingVersion {
+        parent: String,
+        dep: String,
+        req: VersionReq,
+    },
+    /// Crate depended by no others, e.g. root.
+    NoParent { name: String },
+    /// `cargo update` fails to change the package, e.g. conflicting with others.
+    UpdateFailed {
+        name: String,
+        ver: String,
+        reason: String,
+    },
 }
 
 impl AuditError {
     pub fn is_unexpected(&self) -> bool {
         match self {
             Self::Unexpected(_) => true,
-            Self::Functionality(_) => false,
+            Self::Functionality(_) | Self::Candidate(_) => false,
         }
     }
 
     pub fn exit_code(&self) -> i32 {
         match self {
             Self::Unexpected(_) => -1,
-            Self::Functionality(_) => -2,
+            Self::Functionality(_) | Self::Candidate(_) => -2,
         }
     }
 }
 
+impl From<CandidateError> for AuditError {
+    fn from(err: CandidateError) -> Self {
+        Self::Candidate(err)
+    }
+}
+
 impl Display for AuditError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Unexpected(msg) => write!(f, "unexpected error: {msg}"),
             Self::Functionality(msg) => write!(f, "functionality error: {msg}"),
+            Self::Candidate(err) => write!(f, "candidate error: {err}"),
+        }
+    }
+}
+
+impl Display for CandidateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotInDb { name, reason } => {
+                write!(f, "{name} not found in database, {reason}")
+            }
+            Self::NotInIndex { name, ver } => write!(f, "{name}@{ver} not found in index"),
+            Self::DepNotFound { parent, dep } => {
+                write!(f, "{dep} not found in dependencies of {parent}")
+            }
+            Self::NoMatchingVersion { parent, dep, req } => write!(
+                f,
+                "no version of {dep} in database matches `{req}` required by {parent}"
+            ),
+            Self::NoParent { name } => write!(f, "{name} is depended by no others"),
+            Self::UpdateFailed { name, ver, reason } => {
+                write!(
+                    f,
+                    "cannot change {name} to {ver}, cargo update fails: {reason}"
+                )
+            }
         }
     }
 }
@@ -8,13 +8,73 @@ use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet}
 use basic_usages::ruf_check_info::{CheckInfo, UsedRufs};
 
 use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
 use crate::error::AuditError;
 use crate::{spec_cargo, warn_print, RE_CHECKINFO, RUSTV};
 
+/// Env telling the rustc wrapper to scan only these crates, separated by commas,
+/// others are compiled by rustc directly.
+pub const SCAN_ONLY_ENV: &str = "RUF_AUDIT_SCAN_ONLY";
+
 /// rufs usage extract, based on `cargo rustc`.
 pub fn extract(
     config: &mut BuildConfig,
     quiet: bool,
+) -> Result<HashMap<String, UsedRufs>, AuditError> {
+    run_extract(config, None, quiet)
+}
+
+/// Incremental rufs usage extract after the dep tree is changed,
+/// only crates whose compilation units changed are scanned, and merged into `used_rufs`.
+pub fn extract_changed(
+    config: &mut BuildConfig,
+    dm: &mut DepManager,
+    used_rufs: &mut HashMap<String, UsedRufs>,
+    quiet: bool,
+) -> Result<(), AuditError> {
+    let changed = dm.changed_crates()?;
+
+    // Crates dropped from the tree are no longer used.
+    let crates: HashSet<String> = dm
+        .graph()
+        .node_weights()
+        .map(|pkg| pkg.name.as_str().replace('-', "_"))
+        .collect();
+    used_rufs.retain(|crate_name, _| crates.contains(crate_name));
+
+    if changed.is_empty() {
+        return Ok(());
+    }
+
+    // Changed crates may be fresh to cargo, e.g. a version built before, which are not compiled
+    // and not scanned then, so we clean them first.
+    let mut cmd = spec_cargo(RUSTV);
+    cmd.arg("clean");
+    for name in &changed {
+        cmd.args(["-p", name]);
+    }
+    let output = cmd
+        .output()
+        .map_err(|_| AuditError::Unexpected("cannot spawn cargo process".to_string()))?;
+    if !output.status.success() {
+        return Err(AuditError::Unexpected(format!(
+            "cannot clean changed crates: {}",
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    // Old entries are dropped first, changed crates failing to build shall not keep them.
+    let changed: HashSet<String> = changed.iter().map(|name| name.replace('-', "_")).collect();
+    used_rufs.retain(|crate_name, _| !changed.contains(crate_name));
+    used_rufs.extend(run_extract(config, Some(&changed), quiet)?);
+
+    Ok(())
+}
+
+fn run_extract(
+    config: &mut BuildConfig,
+    scan_only: Option<&HashSet<String>>,
+    quiet: bool,
 ) -> Result<HashMap<String, UsedRufs>, AuditError> {
     let mut cmd = spec_cargo(RUSTV);
     cmd.args(["rustc", "-Z", "unstable-options", "--keep-going"]);
@@ -23,14 +83,18 @@ pub fn extract(
     }
 
     let path = current_exe()
-        .map_err(|_| AuditError::Unexpected(format!("cannot get current exe path")))?;
+        .map_err(|_| AuditError::Unexpected("cannot get current exe path".to_string()))?;
     cmd.env("RUSTC_WRAPPER", &path);
+    if let Some(scan_only) = scan_only {
+        let crates: Vec<&str> = scan_only.iter().map(|name| name.as_str()).collect();
+        cmd.env(SCAN_ONLY_ENV, crates.join(","));
+    }
 
     let mut child = cmd
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .map_err(|_| AuditError::Unexpected(format!("cannot spawn cargo process")))?;
+        .map_err(|_| AuditError::Unexpected("cannot spawn cargo process".to_string()))?;
 
     // verbose stderr infos
     if config.is_verbose() {
@@ -51,7 +115,6 @@ pub fn extract(
 
         let err = stderr
             .lines()
-            .into_iter()
             .find(|line| line.trim_start().starts_with("error"))
             .unwrap_or("unknown error");
 
@@ -59,7 +122,11 @@ pub fn extract(
         // since we only cares ruf usage, rather than syntax error or things like that.
         //
         // However this could cause problems, when compile just fails, we may left out some ruf usages.
-        warn_print!(quiet, "Building issues", &format!("extraction incomplete, due to: {err}"));
+        warn_print!(
+            quiet,
+            "Building issues",
+            &format!("extraction incomplete, due to: {err}")
+        );
         // return Err(AuditError::Unexpected(format!("cargo failure: {err}")));
     }
 
@@ -87,5 +154,5 @@ pub fn extract(
         used_rufs.insert(crate_name, UsedRufs::new(rufs.into_iter().collect()));
     }
 
-    return Ok(used_rufs);
+    Ok(used_rufs)
 }
@@ -0,0 +1,505 @@
+//! This file finds cargo features that enable unusable rufs, e.g. `nightly` or `unstable` ones,
+//! and turns them off in local manifests, as an alternative to changing versions.
+//!
+//! A feature is to blame if either the crate no longer uses unusable rufs without it,
+//! through `cfg_attr(feature = "..", feature(..))`, or it pulls in an optional dependency which does.
+//! Features of dependents forwarding blamed ones, e.g. `dep/feat`, are turned off along with them.
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::ruf_check_info::UsedRufs;
+
+use cargo_metadata::{DependencyKind, Metadata, MetadataCommand, Package, PackageId};
+use toml_edit::{value, Array};
+
+use crate::build_config::BuildConfig;
+use crate::cache;
+use crate::dep_manager::DepManager;
+use crate::error::AuditError;
+use crate::extract::extract_changed;
+use crate::fix_plan::FixStep;
+use crate::manifest;
+use crate::{info_print, warn_print, RUSTV};
+
+/// Features to be turned off through a dependency entry, and who turns them on.
+struct FeatureFix {
+    /// crate whose features are to blame.
+    krate: String,
+    /// features to blame, turned on through the entry.
+    features: Vec<String>,
+    /// the crate enabling the features.
+    enabled_by: String,
+    /// manifest of the enabling crate, if it is a local one.
+    manifest: Option<String>,
+    /// dependency key in the manifest, which is the renamed one if renamed.
+    dep_key: String,
+    /// features removed from the entry, which turn on blamed ones.
+    remove: Vec<String>,
+    /// whether default features are turned off.
+    by_default: bool,
+    /// features turned on through the removed ones, which are kept.
+    keep: Vec<String>,
+}
+
+/// Suggest disabling features enabling unusable rufs, and apply them if configured.
+/// Returns the used rufs, re-extracted if any manifest is changed.
+pub fn feature_fix(
+    config: &mut BuildConfig,
+    mut used_rufs: HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<HashMap<String, UsedRufs>, AuditError> {
+    let metadata = load_metadata()?;
+    let resolved = resolved_features(&metadata);
+    let blamed = find_blamed(config, &metadata, &resolved, &used_rufs)?;
+    let fixes = find_feature_fixes(&metadata, &resolved, &blamed);
+    if fixes.is_empty() {
+        return Ok(used_rufs);
+    }
+
+    for fix in &fixes {
+        let how = match (fix.by_default, fix.remove.is_empty()) {
+            (true, true) => "default features",
+            (true, false) => "default and other features",
+            (false, _) => "features",
+        };
+        let whom = match &fix.manifest {
+            Some(manifest) => format!("`{manifest}`"),
+            None => format!("{} (not a local crate)", fix.enabled_by),
+        };
+        info_print!(
+            queit,
+            "\tSuggest",
+            &format!(
+                "turn off feature {} of {}, enabled through {how} of `{}` in {whom}",
+                quoted(&fix.features),
+                fix.krate,
+                fix.dep_key
+            )
+        );
+    }
+
+    if !config.is_feature_fix() {
+        if fixes.iter().any(|fix| fix.manifest.is_some()) {
+            info_print!(
+                queit,
+                "\tSuggest",
+                "pass `--disable-features` to apply them to local manifests"
+            );
+        }
+        return Ok(used_rufs);
+    }
+
+    // Features also turned on by non-local crates cannot be turned off.
+    let (fixable, unfixable): (Vec<_>, Vec<_>) = blamed.into_iter().partition(|blamed| {
+        let fixes = find_feature_fixes(&metadata, &resolved, &[blamed.clone()]);
+        !fixes.is_empty() && fixes.iter().all(|fix| fix.manifest.is_some())
+    });
+    for (pkg, feature) in unfixable {
+        warn_print!(
+            queit,
+            "\tFixing",
+            &format!(
+                "feature `{feature}` of {}@{} is not only turned on by local crates, left as it is",
+                pkg.name, pkg.version
+            )
+        );
+    }
+    let fixes = find_feature_fixes(&metadata, &resolved, &fixable);
+    if fixes.is_empty() {
+        return Ok(used_rufs);
+    }
+
+    let snapshot = dm.snapshot()?;
+    let mut changes = vec![];
+    for fix in &fixes {
+        let manifest = fix
+            .manifest
+            .as_ref()
+            .expect("Fatal, fix of non-local crate");
+        match disable_feature(manifest, fix) {
+            Ok(change) => changes.push((manifest.to_string(), change)),
+            Err(e) => {
+                warn_print!(queit, "\tFixing", &format!("cannot edit `{manifest}`: {e}"));
+                dm.restore(&snapshot)?;
+                return Ok(used_rufs);
+            }
+        }
+    }
+
+    // Fixes are only recorded when all blamed features are turned off,
+    // they may still be turned on, e.g. by features of the workspace root.
+    let still_on = still_enabled(&fixable)?;
+    if !still_on.is_empty() {
+        warn_print!(
+            queit,
+            "\tFixing",
+            &format!(
+                "{} still turned on after the changes, changes reverted",
+                still_on.join(", ")
+            )
+        );
+        dm.restore(&snapshot)?;
+        return Ok(used_rufs);
+    }
+
+    for (manifest, change) in changes {
+        info_print!(queit, "\tFixing", &format!("edit `{manifest}`, {change}"));
+        dm.push_step(FixStep::Manifest {
+            path: manifest,
+            change,
+        });
+    }
+
+    info_print!(queit, "\tFixing", "rechecking ruf issues");
+    dm.reload()?;
+    extract_changed(config, dm, &mut used_rufs, queit)?;
+    Ok(used_rufs)
+}
+
+fn load_metadata() -> Result<Metadata, AuditError> {
+    MetadataCommand::new()
+        .env("RUSTUP_TOOLCHAIN", RUSTV)
+        .exec()
+        .map_err(|e| AuditError::Unexpected(format!("cannot load metadata: {e}")))
+}
+
+fn resolved_features(metadata: &Metadata) -> HashMap<&PackageId, &Vec<String>> {
+    metadata
+        .resolve
+        .iter()
+        .flat_map(|resolve| resolve.nodes.iter())
+        .map(|node| (&node.id, &node.features))
+        .collect()
+}
+
+/// Features enabling unusable rufs, either of the issued crates, or of their dependents.
+fn find_blamed<'m>(
+    config: &BuildConfig,
+    metadata: &'m Metadata,
+    resolved: &HashMap<&PackageId, &Vec<String>>,
+    used_rufs: &HashMap<String, UsedRufs>,
+) -> Result<Vec<(&'m Package, String)>, AuditError> {
+    let mut issued: Vec<_> = used_rufs
+        .iter()
+        .filter(|(_, rufs)| !config.rufs_usable(rufs))
+        .map(|(name, _)| name)
+        .collect();
+    issued.sort();
+
+    let mut blamed = vec![];
+    for crate_name in issued {
+        for pkg in metadata
+            .packages
+            .iter()
+            .filter(|pkg| pkg.name.replace('-', "_") == *crate_name)
+        {
+            // Features turning on rufs of the crate itself.
+            for feature in cfg_features(config, pkg)? {
+                blamed.push((pkg, feature));
+            }
+
+            // Features of dependents pulling in the crate.
+            for p_pkg in &metadata.packages {
+                let Some(p_features) = resolved.get(&p_pkg.id) else {
+                    continue;
+                };
+                for dep in p_pkg.dependencies.iter().filter(|dep| {
+                    dep.optional && dep.name == pkg.name && dep.req.matches(&pkg.version)
+                }) {
+                    let key = dep.rename.as_ref().unwrap_or(&dep.name);
+                    for feature in p_features.iter().filter(|feature| {
+                        p_pkg.features.get(*feature).map_or(false, |enables| {
+                            enables.iter().any(|enable| enables_dep(enable, key))
+                        })
+                    }) {
+                        blamed.push((p_pkg, feature.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::default();
+    blamed.retain(|(pkg, feature)| seen.insert((pkg.id.clone(), feature.clone())));
+    Ok(blamed)
+}
+
+/// Dependency entries to change, so that none of the blamed features is turned on.
+///
+/// Blamed features may be forwarded by features of dependents, e.g. `dep/feat`,
+/// then the forwarding ones are turned off as well.
+fn find_feature_fixes(
+    metadata: &Metadata,
+    resolved: &HashMap<&PackageId, &Vec<String>>,
+    blamed: &[(&Package, String)],
+) -> Vec<FeatureFix> {
+    let mut turned_off: HashMap<&PackageId, (&Package, HashSet<String>)> = HashMap::default();
+    let mut stack = blamed.to_vec();
+    while let Some((pkg, feature)) = stack.pop() {
+        let (_, features) = turned_off
+            .entry(&pkg.id)
+            .or_insert_with(|| (pkg, HashSet::default()));
+        if features.insert(feature.clone()) {
+            stack.extend(forwarders(metadata, resolved, pkg, &feature));
+        }
+    }
+
+    let mut turned_off: Vec<_> = turned_off.into_values().collect();
+    turned_off.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+    turned_off
+        .into_iter()
+        .flat_map(|(pkg, features)| enablers(metadata, pkg, &features))
+        .collect()
+}
+
+/// Enabled features of a crate, without which it uses no unusable rufs.
+fn cfg_features(config: &BuildConfig, pkg: &Package) -> Result<Vec<String>, AuditError> {
+    // Rufs of local crates are not recorded in database.
+    if pkg.source.is_none() {
+        return Ok(vec![]);
+    }
+    let Some(cond_rufs) = cache::get_rufs_with_crate_name(&pkg.name)
+        .ok()
+        .and_then(|mut rufs| rufs.remove(&pkg.version))
+    else {
+        return Ok(vec![]);
+    };
+
+    let features = config.enabled_features(&pkg.name);
+    let jobs = features
+        .iter()
+        .map(|feature| {
+            (
+                pkg.name.as_str(),
+                &pkg.version,
+                cond_rufs.clone(),
+                Some(feature.as_str()),
+            )
+        })
+        .collect();
+    let used_rufs = config.filter_rufs_many(jobs)?;
+
+    Ok(features
+        .into_iter()
+        .zip(used_rufs)
+        .filter(|(_, used_rufs)| config.rufs_usable(used_rufs))
+        .map(|(feature, _)| feature)
+        .collect())
+}
+
+/// Whether a feature entry, e.g. `dep:foo`, `foo` or `foo/bar`, turns on the dependency.
+fn enables_dep(enable: &str, key: &str) -> bool {
+    let enable = enable.strip_prefix("dep:").unwrap_or(enable);
+    let dep = enable.split('/').next().unwrap_or(enable);
+    dep.strip_suffix('?').is_none() && dep == key
+}
+
+/// The feature a `dep/feat` or `dep?/feat` entry turns on, if it is of the dependency.
+fn forwards<'e>(enable: &'e str, key: &str) -> Option<&'e str> {
+    let (dep, feature) = enable.split_once('/')?;
+    (dep.strip_suffix('?').unwrap_or(dep) == key).then_some(feature)
+}
+
+/// Enabled features of dependents, which turn on the feature of the crate through `dep/feat`.
+fn forwarders<'m>(
+    metadata: &'m Metadata,
+    resolved: &HashMap<&PackageId, &Vec<String>>,
+    pkg: &Package,
+    feature: &str,
+) -> Vec<(&'m Package, String)> {
+    let mut found = vec![];
+    for p_pkg in &metadata.packages {
+        let Some(p_features) = resolved.get(&p_pkg.id) else {
+            continue;
+        };
+        for dep in p_pkg.dependencies.iter().filter(|dep| {
+            dep.name == pkg.name
+                && dep.req.matches(&pkg.version)
+                && dep.kind != DependencyKind::Development
+        }) {
+            let key = dep.rename.as_ref().unwrap_or(&dep.name);
+            for p_feature in p_features.iter().filter(|p_feature| {
+                p_pkg.features.get(*p_feature).map_or(false, |enables| {
+                    enables.iter().any(|enable| {
+                        forwards(enable, key).map_or(false, |f| closure(pkg, f).contains(feature))
+                    })
+                })
+            }) {
+                found.push((p_pkg, p_feature.clone()));
+            }
+        }
+    }
+
+    found
+}
+
+/// Dependents turning on the features of the crate, directly or through default features.
+fn enablers(metadata: &Metadata, pkg: &Package, features: &HashSet<String>) -> Vec<FeatureFix> {
+    let turns_on = |feature: &str| !closure(pkg, feature).is_disjoint(features);
+
+    let mut fixes = vec![];
+    for p_pkg in &metadata.packages {
+        for dep in p_pkg.dependencies.iter().filter(|dep| {
+            dep.name == pkg.name
+                && dep.req.matches(&pkg.version)
+                && dep.kind != DependencyKind::Development
+        }) {
+            let remove: Vec<String> = dep
+                .features
+                .iter()
+                .filter(|f| turns_on(f))
+                .cloned()
+                .collect();
+            let by_default = dep.uses_default_features && turns_on("default");
+            if remove.is_empty() && !by_default {
+                continue;
+            }
+
+            // Features turned on through those turned off, which are to be kept if innocent.
+            let mut lost: HashSet<String> = remove.iter().flat_map(|f| closure(pkg, f)).collect();
+            if by_default {
+                lost.extend(closure(pkg, "default"));
+            }
+            let mut blamed: Vec<_> = features.intersection(&lost).cloned().collect();
+            blamed.sort();
+
+            let left: HashSet<String> = dep
+                .features
+                .iter()
+                .filter(|f| !remove.contains(f))
+                .flat_map(|f| closure(pkg, f))
+                .collect();
+            let innocent: Vec<_> = lost
+                .into_iter()
+                .filter(|f| {
+                    f != "default"
+                        && pkg.features.contains_key(f)
+                        && !turns_on(f)
+                        && !left.contains(f)
+                })
+                .collect();
+            // Only the outermost ones, the others are turned on through them.
+            let mut keep: Vec<_> = innocent
+                .iter()
+                .filter(|f| {
+                    !innocent
+                        .iter()
+                        .any(|g| g != *f && closure(pkg, g).contains(*f))
+                })
+                .cloned()
+                .collect();
+            keep.sort();
+
+            fixes.push(FeatureFix {
+                krate: format!("{}@{}", pkg.name, pkg.version),
+                features: blamed,
+                enabled_by: format!("{}@{}", p_pkg.name, p_pkg.version),
+                manifest: p_pkg
+                    .source
+                    .is_none()
+                    .then(|| p_pkg.manifest_path.to_string()),
+                dep_key: dep.rename.as_ref().unwrap_or(&dep.name).to_string(),
+                remove,
+                by_default,
+                keep,
+            });
+        }
+    }
+
+    fixes
+}
+
+/// The feature and features turned on by it, transitively.
+fn closure(pkg: &Package, feature: &str) -> HashSet<String> {
+    let mut features = HashSet::default();
+    let mut stack = vec![feature.to_string()];
+    while let Some(feature) = stack.pop() {
+        if !features.insert(feature.clone()) {
+            continue;
+        }
+        for enable in pkg.features.get(&feature).into_iter().flatten() {
+            if pkg.features.contains_key(enable) {
+                stack.push(enable.clone());
+            }
+        }
+    }
+
+    features
+}
+
+/// Blamed features still turned on, as of current manifests.
+fn still_enabled(blamed: &[(&Package, String)]) -> Result<Vec<String>, AuditError> {
+    let metadata = load_metadata()?;
+    let resolved = resolved_features(&metadata);
+
+    Ok(blamed
+        .iter()
+        .filter(|(pkg, feature)| {
+            metadata.packages.iter().any(|cur| {
+                cur.name == pkg.name
+                    && cur.version == pkg.version
+                    && resolved
+                        .get(&cur.id)
+                        .map_or(false, |features| features.contains(feature))
+            })
+        })
+        .map(|(pkg, feature)| format!("feature `{feature}` of {}@{}", pkg.name, pkg.version))
+        .collect())
+}
+
+fn quoted(features: &[String]) -> String {
+    features
+        .iter()
+        .map(|f| format!("`{f}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Turn off the features in the manifest, returns the change made.
+fn disable_feature(path: &str, fix: &FeatureFix) -> Result<String, AuditError> {
+    let mut doc = manifest::load(path)?;
+    let table = manifest::dependency_table(&mut doc, &fix.dep_key)?;
+
+    let mut features: Vec<String> = table
+        .get("features")
+        .and_then(|features| features.as_array())
+        .map(|features| {
+            features
+                .iter()
+                .filter_map(|f| f.as_str())
+                .filter(|f| !fix.remove.iter().any(|removed| removed == f))
+                .map(|f| f.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    for f in &fix.keep {
+        if !features.contains(f) {
+            features.push(f.clone());
+        }
+    }
+
+    let mut change = if fix.by_default {
+        table.insert("default-features", value(false));
+        format!("turn off default features of `{}`", fix.dep_key)
+    } else {
+        format!(
+            "remove features {:?} from dependency `{}`",
+            fix.remove, fix.dep_key
+        )
+    };
+    if fix.by_default && !fix.remove.is_empty() {
+        change.push_str(&format!(", removing {:?}", fix.remove));
+    }
+    if !fix.keep.is_empty() {
+        change.push_str(&format!(", keeping {:?}", fix.keep));
+    }
+    if features.is_empty() {
+        table.remove("features");
+    } else {
+        table.insert("features", value(Array::from_iter(features)));
+    }
+
+    manifest::save(path, &doc)?;
+
+    Ok(change)
+}
@@ -0,0 +1,77 @@
+//! This file describes fix plans, i.e. what the fixes do to the dependency tree.
+
+use basic_usages::rustc_version::get_nightly_version;
+use similar::TextDiff;
+
+use crate::{info_print, warn_print};
+
+/// A single change to the dependency tree.
+#[derive(Debug)]
+pub enum FixStep {
+    /// Change package version through `cargo update --precise`.
+    Update {
+        name: String,
+        from: String,
+        to: String,
+    },
+    /// Edit a local manifest.
+    Manifest { path: String, change: String },
+}
+
+impl std::fmt::Display for FixStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Update { name, from, to } => write!(f, "change {name}@{from} to {name}@{to}"),
+            Self::Manifest { path, change } => write!(f, "edit `{path}`, {change}"),
+        }
+    }
+}
+
+/// All changes made during a fix, and the rustc version chosen, if any.
+pub struct FixPlan<'p> {
+    steps: &'p [FixStep],
+    rustc: Option<u32>,
+}
+
+impl<'p> FixPlan<'p> {
+    pub fn new(steps: &'p [FixStep], rustc: Option<u32>) -> Self {
+        Self { steps, rustc }
+    }
+
+    /// Print the plan, and the lockfile diff it leads to, even in quiet mode.
+    pub fn show(&self, origin_lock: &str, fixed_lock: &str) {
+        info_print!(false, "Plan", "changes to be done (dry run)");
+        if self.steps.is_empty() {
+            info_print!(false, "\tStep", "no dependency changes");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            info_print!(false, "\tStep", &format!("{}. {step}", i + 1));
+        }
+
+        match self.rustc {
+            Some(rustc) if (rustc as usize) < basic_usages::ruf_lifetime::RUSTC_VER_NUM => {
+                info_print!(
+                    false,
+                    "\tRustc",
+                    &format!("1.{rustc}.* ({})", get_nightly_version(rustc))
+                );
+            }
+            Some(rustc) => info_print!(false, "\tRustc", &format!("1.{rustc}.*")),
+            None => info_print!(false, "\tRustc", "unchanged"),
+        }
+
+        if origin_lock == fixed_lock {
+            warn_print!(false, "\tDiff", "`Cargo.lock` unchanged");
+            return;
+        }
+
+        info_print!(false, "\tDiff", "`Cargo.lock` changes:");
+        let diff = TextDiff::from_lines(origin_lock, fixed_lock);
+        print!(
+            "{}",
+            diff.unified_diff()
+                .context_radius(3)
+                .header("a/Cargo.lock", "b/Cargo.lock")
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! This file fixes ruf issues by changing rustc and dep tree together, which lies between
+//! fixing dep tree against current rustc, and changing rustc with a minimal dep tree.
+//!
+//! For each rustc in the lifetime table, the dependency changes needed are estimated by solving
+//! a usable dep tree against that rustc, and the rustc needing the fewest changes is chosen.
+
+use std::cmp::Reverse;
+
+use basic_usages::external::fxhash::FxHashMap as HashMap;
+use basic_usages::external::semver::Version;
+use basic_usages::ruf_check_info::UsedRufs;
+use basic_usages::ruf_lifetime::RUSTC_VER_NUM;
+
+use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
+use crate::error::AuditError;
+use crate::extract::extract_changed;
+use crate::solver::Solver;
+use crate::toolchain::toolchain_channel;
+use crate::{info_print, warn_print};
+
+/// How many plans are shown, including the chosen one.
+const ALTERNATIVES: usize = 5;
+
+/// A rustc, and the dependency changes it needs.
+struct HybridPlan {
+    rustc_version: u32,
+    /// packages to be changed, as `(name, current version, new version)`.
+    updates: Vec<(String, Version, Version)>,
+}
+
+/// Fix with the rustc needing the fewest dependency changes, returns the rustc chosen.
+/// Rustc version in config is left unchanged.
+pub fn hybrid_fix(
+    config: &mut BuildConfig,
+    used_rufs: &mut HashMap<String, UsedRufs>,
+    dm: &mut DepManager,
+    queit: bool,
+) -> Result<u32, AuditError> {
+    let cur_rustc = config.get_rust_version();
+    let plans = search_plans(config, used_rufs, dm, queit);
+    config.set_rust_version(cur_rustc);
+    let plans = plans?;
+
+    let Some(best) = plans.first() else {
+        return Err(AuditError::Functionality(
+            "no rustc can be used with a fixed dependency tree".to_string(),
+        ));
+    };
+
+    for (i, plan) in plans.iter().enumerate() {
+        let channel = toolchain_channel(plan.rustc_version, config.is_stable_only());
+        let current = if plan.rustc_version == cur_rustc {
+            ", current rustc"
+        } else {
+            ""
+        };
+        info_print!(
+            queit,
+            "\tHybrid",
+            &format!(
+                "{}. {channel} (rustc 1.{}.*{current}): change {} packages",
+                i + 1,
+                plan.rustc_version,
+                plan.updates.len()
+            )
+        );
+    }
+
+    let channel = toolchain_channel(best.rustc_version, config.is_stable_only());
+    info_print!(
+        queit,
+        "\tHybrid",
+        &format!(
+            "choose {channel}, with {} packages changed",
+            best.updates.len()
+        )
+    );
+    let updates: Vec<(String, String, String)> = best
+        .updates
+        .iter()
+        .map(|(name, ver, fix_ver)| {
+            info_print!(
+                queit,
+                "\tHybrid",
+                &format!("changing {name}@{ver} to {name}@{fix_ver}")
+            );
+            (name.clone(), ver.to_string(), fix_ver.to_string())
+        })
+        .collect();
+
+    dm.update_pkgs(&updates)?;
+
+    // New crates may come with the changes, which are unknown to the estimation.
+    info_print!(queit, "\tHybrid", "rechecking ruf issues");
+    extract_changed(config, dm, used_rufs, queit)?;
+    config.set_rust_version(best.rustc_version);
+    let usable = used_rufs.iter().all(|(_, rufs)| config.rufs_usable(rufs));
+    config.set_rust_version(cur_rustc);
+    if !usable {
+        return Err(AuditError::Functionality(format!(
+            "ruf issues remain with {channel} after changing dependencies"
+        )));
+    }
+
+    Ok(best.rustc_version)
+}
+
+/// Solve dep trees against each rustc, returns the plans needing fewest changes, fewest first.
+///
+/// Crates unusable with a rustc must be changed, which bounds the changes needed from below,
+/// so rustc are tried in that order, and the search stops once no better plan can be found.
+fn search_plans(
+    config: &mut BuildConfig,
+    used_rufs: &HashMap<String, UsedRufs>,
+    dm: &DepManager,
+    queit: bool,
+) -> Result<Vec<HybridPlan>, AuditError> {
+    let usable_rustc: Vec<_> = used_rufs
+        .values()
+        .map(|rufs| config.usable_rustc_for_rufs(rufs))
+        .collect();
+    let mut bounds: Vec<(usize, u32)> = (0..RUSTC_VER_NUM as u32)
+        .map(|rustc_version| {
+            let issued = usable_rustc
+                .iter()
+                .filter(|usable| !usable.contains(&rustc_version))
+                .count();
+            (issued, rustc_version)
+        })
+        .collect();
+    // Newer rustc first among the same bound.
+    bounds.sort_by_key(|(issued, rustc_version)| (*issued, Reverse(*rustc_version)));
+
+    let mut plans: Vec<HybridPlan> = vec![];
+    for (issued, rustc_version) in bounds {
+        if plans.len() >= ALTERNATIVES && issued > plans[ALTERNATIVES - 1].updates.len() {
+            break;
+        }
+
+        config.set_rust_version(rustc_version);
+        let updates = if issued == 0 {
+            vec![]
+        } else {
+            match Solver::new(config, dm, used_rufs).solve() {
+                Ok(updates) => updates,
+                Err(e) if e.is_unexpected() => return Err(e),
+                Err(e) => {
+                    if config.is_verbose() {
+                        warn_print!(
+                            queit,
+                            "\tHybrid",
+                            &format!("rustc 1.{rustc_version}.*: {e}")
+                        );
+                    }
+                    continue;
+                }
+            }
+        };
+
+        plans.push(HybridPlan {
+            rustc_version,
+            updates,
+        });
+        plans.sort_by_key(|plan| (plan.updates.len(), Reverse(plan.rustc_version)));
+        plans.truncate(ALTERNATIVES);
+    }
+
+    Ok(plans)
+}
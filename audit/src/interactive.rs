@@ -0,0 +1,207 @@
+//! This file asks user to approve each down fix step in interactive mode,
+//! and records the decisions, so that they can be replayed non-interactively later.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+use basic_usages::external::fxhash::FxHashMap as HashMap;
+use basic_usages::external::semver::Version;
+use basic_usages::external::serde::{Deserialize, Serialize};
+use basic_usages::external::serde_json;
+
+use crate::build_config::BuildConfig;
+use crate::error::AuditError;
+use crate::{info_print, warn_print};
+
+/// What to do with an issued crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Choice {
+    /// Change to the version.
+    Update(Version),
+    /// Leave the crate as it is.
+    Skip,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(crate = "basic_usages::external::serde")]
+struct Decision {
+    /// the issued crate, as `name@version`.
+    #[serde(rename = "crate")]
+    krate: String,
+    /// the version changed to, `None` if skipped.
+    version: Option<String>,
+}
+
+pub struct Decisions {
+    /// decisions to replay, keyed by issued crates.
+    replay: HashMap<String, Choice>,
+    /// decisions made in this run, in order.
+    made: Vec<Decision>,
+    interactive: bool,
+}
+
+impl Decisions {
+    /// Load decisions to replay, if any.
+    pub fn new(config: &BuildConfig) -> Result<Self, AuditError> {
+        let mut replay = HashMap::default();
+        if let Some(path) = config.get_replay_file() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| AuditError::Functionality(format!("cannot read `{path}`: {e}")))?;
+            let decisions: Vec<Decision> = serde_json::from_str(&content)
+                .map_err(|e| AuditError::Functionality(format!("cannot parse `{path}`: {e}")))?;
+            for decision in decisions {
+                let choice = match decision.version {
+                    Some(ver) => Choice::Update(Version::parse(&ver).map_err(|e| {
+                        AuditError::Functionality(format!("cannot parse `{path}`: {e}"))
+                    })?),
+                    None => Choice::Skip,
+                };
+                replay.insert(decision.krate, choice);
+            }
+        }
+
+        Ok(Self {
+            replay,
+            made: vec![],
+            interactive: config.is_interactive(),
+        })
+    }
+
+    /// Decide what to do with the issued crate, among ranked usable candidates.
+    /// The preferred candidate is chosen unless replayed or asked otherwise.
+    pub fn decide(
+        &mut self,
+        issued: &str,
+        unusable_rufs: &[String],
+        candidates: &[Version],
+        queit: bool,
+    ) -> Result<Choice, AuditError> {
+        let replayed = self
+            .replay
+            .get(issued)
+            .cloned()
+            .filter(|choice| match choice {
+                Choice::Update(ver) => candidates.contains(ver),
+                Choice::Skip => true,
+            });
+
+        let choice = match replayed {
+            Some(choice) => {
+                info_print!(queit, "\tReplay", &format!("{issued}: {choice}"));
+                choice
+            }
+            None => {
+                if self.replay.contains_key(issued) {
+                    warn_print!(
+                        queit,
+                        "\tReplay",
+                        &format!("recorded version of {issued} is no longer usable")
+                    );
+                }
+                if self.interactive {
+                    ask(issued, unusable_rufs, candidates)?
+                } else {
+                    match candidates.first() {
+                        Some(ver) => Choice::Update(ver.clone()),
+                        None => Choice::Skip,
+                    }
+                }
+            }
+        };
+
+        self.made.push(Decision {
+            krate: issued.to_string(),
+            version: match &choice {
+                Choice::Update(ver) => Some(ver.to_string()),
+                Choice::Skip => None,
+            },
+        });
+        Ok(choice)
+    }
+
+    /// Save decisions made in interactive mode.
+    pub fn save(&self, config: &BuildConfig, queit: bool) -> Result<(), AuditError> {
+        if !self.interactive || self.made.is_empty() {
+            return Ok(());
+        }
+
+        let path = config.get_choices_file();
+        let content = serde_json::to_string_pretty(&self.made)
+            .map_err(|e| AuditError::Unexpected(format!("cannot serialize decisions: {e}")))?;
+        fs::write(path, content)
+            .map_err(|e| AuditError::Unexpected(format!("cannot write `{path}`: {e}")))?;
+
+        info_print!(
+            queit,
+            "\tRecord",
+            &format!("decisions are saved in `{path}`, replay them with `--replay {path}`")
+        );
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Choice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Choice::Update(ver) => write!(f, "change to {ver}"),
+            Choice::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Show the issue and ask user, candidates are shown in preferred order.
+fn ask(
+    issued: &str,
+    unusable_rufs: &[String],
+    candidates: &[Version],
+) -> Result<Choice, AuditError> {
+    // Always shown, or we wait silently in quiet mode.
+    warn_print!(false, "\tIssue", &format!("{issued} uses unusable rufs"));
+    for ruf in unusable_rufs {
+        info_print!(false, "\t\tRuf", &format!("`{ruf}`"));
+    }
+    for (i, ver) in candidates.iter().enumerate() {
+        let preferred = if i == 0 { " (preferred)" } else { "" };
+        info_print!(
+            false,
+            "\t\tCandidate",
+            &format!("{}. {ver}{preferred}", i + 1)
+        );
+    }
+
+    let options = if candidates.is_empty() {
+        "[s]kip, [a]bort"
+    } else {
+        "enter to accept, 1-N to pick, [s]kip, [a]bort"
+    };
+    loop {
+        info_print!(
+            false,
+            "\tConfirm",
+            &format!("what to do with {issued}? {options}")
+        );
+        io::stdout().flush().ok();
+
+        let mut answer = String::new();
+        let read = io::stdin()
+            .lock()
+            .read_line(&mut answer)
+            .map_err(|e| AuditError::Unexpected(format!("cannot read answer: {e}")))?;
+        // No more input, treated as abort rather than looping forever.
+        if read == 0 {
+            return Err(AuditError::Functionality("aborted, no input".to_string()));
+        }
+
+        match answer.trim().to_lowercase().as_str() {
+            "" if !candidates.is_empty() => return Ok(Choice::Update(candidates[0].clone())),
+            "s" | "skip" => return Ok(Choice::Skip),
+            "a" | "abort" => return Err(AuditError::Functionality("aborted by user".to_string())),
+            answer => match answer.parse::<usize>() {
+                Ok(i) if (1..=candidates.len()).contains(&i) => {
+                    return Ok(Choice::Update(candidates[i - 1].clone()))
+                }
+                _ => warn_print!(false, "\tConfirm", &format!("invalid answer `{answer}`")),
+            },
+        }
+    }
+}
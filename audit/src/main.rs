@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use std::process::{exit, Command};
 
 use ansi_term::{Color, Style};
+use basic_usages::rustc_version::get_rustc_version;
 use getopts::Options;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -14,7 +15,7 @@ use slog_async::Async;
 use sloggers::{file::FileLoggerBuilder, Build};
 
 mod extract;
-use extract::extract;
+use extract::{extract, SCAN_ONLY_ENV};
 
 mod audit;
 use audit::{audit, test};
@@ -22,15 +23,45 @@ use audit::{audit, test};
 mod error;
 
 mod build_config;
-use build_config::BuildConfig;
+use build_config::{BuildConfig, CandidatePolicy, Grade};
 
 mod dep_manager;
 
+mod solver;
+
+mod up_fix;
+
+mod fix_plan;
+
+mod report;
+
+mod transaction;
+
+mod toolchain;
+use toolchain::{is_stable_toolchain, parse_toolchains};
+
+mod feature_fix;
+
+mod manifest;
+
+mod req_fix;
+
+mod patch_fix;
+
+mod interactive;
+
+mod cache;
+
+mod hybrid_fix;
+
+mod scratch;
+
 // Some predefined things
 lazy_static! {
     pub static ref RE_USEDFEATS: Regex = Regex::new(r"FDelimiter::\{(.*?)\}::FDelimiter").unwrap();
     pub static ref RE_CHECKINFO: Regex = Regex::new(r"CDelimiter::\{(.*?)\}::CDelimiter").unwrap();
-    pub static ref RE_RUSTC_VRESION: Regex = Regex::new(r"rustc\s+1\.(\d+)\.\d+-nightly").unwrap();
+    pub static ref RE_RUSTC_VRESION: Regex =
+        Regex::new(r"rustc\s+1\.(\d+)\.\d+(-nightly)?").unwrap();
     pub static ref BOLD_RED: Style = Style::new().bold().fg(Color::Red);
     pub static ref BOLD_YELLOW: Style = Style::new().bold().fg(Color::Yellow);
     pub static ref BOLD_GREEN: Style = Style::new().bold().fg(Color::Green);
@@ -70,8 +101,19 @@ fn main() {
     if args.len() >= 2 && args[1] == config.get_audit_rustc_path() {
         // debug!(LOGGER, "scanner args: {args:?}");
 
+        // Only some crates are scanned in incremental extraction.
+        let skipped = env::var(SCAN_ONLY_ENV).is_ok_and(|scan_only| {
+            let crate_name = args
+                .iter()
+                .position(|arg| arg == "--crate-name")
+                .and_then(|i| args.get(i + 1));
+            crate_name.map_or(true, |crate_name| {
+                !scan_only.split(',').any(|name| name == crate_name)
+            })
+        });
+
         // We directly use rustc to do some information fetch
-        let status = if args[2] == "-" {
+        let status = if args[2] == "-" || skipped {
             // debug!(LOGGER, "Use rustc, inherit std");
             spec_rustc(RUSTV)
                 .args(&args[2..])
@@ -108,6 +150,128 @@ fn main() {
         "quick-fix",
         "Fix by changing rustc and using oldest dep tree",
     );
+    opts.optflag(
+        "",
+        "solver",
+        "Fix by solving a usable dep tree as a whole, or prove there is none",
+    );
+    opts.optflag(
+        "",
+        "hybrid",
+        "Fix with the rustc needing fewest dependency changes, ranked with alternatives",
+    );
+    opts.optopt(
+        "",
+        "toolchain",
+        "Audit against given toolchain rather than the default one",
+        "nightly-YYYY-MM-DD|1.N",
+    );
+    opts.optopt(
+        "",
+        "toolchains",
+        "Find one dep tree usable with all given toolchains, separated by commas, ranges as `A..B`",
+        "TOOLCHAIN,..",
+    );
+    opts.optflag(
+        "",
+        "stable",
+        "Only accept rufs already stabilized, to find a dep tree for stable rustc",
+    );
+    opts.optopt(
+        "",
+        "candidate-policy",
+        "Which candidate version to prefer when fixing a crate (default downgrade)",
+        "downgrade|upgrade|closest",
+    );
+    opts.optopt(
+        "",
+        "max-depth",
+        "Max layers of parents, and changes in a row, tried by up fix (default 10)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "time-budget",
+        "Time budget of each up fix search or solver run, in seconds (default 300)",
+        "SECS",
+    );
+    opts.optflag(
+        "",
+        "dry-run",
+        "Show the fix plan and `Cargo.lock` diff, without changing `Cargo.lock`",
+    );
+    opts.optflag(
+        "",
+        "write-toolchain",
+        "Write the rustc chosen by rustc fix into `rust-toolchain.toml`",
+    );
+    opts.optflag(
+        "",
+        "verify",
+        "Build check the fix with each installed candidate rustc",
+    );
+    opts.optflag(
+        "",
+        "disable-features",
+        "Turn off cargo features enabling unusable rufs in local manifests",
+    );
+    opts.optflag(
+        "",
+        "relax-reqs",
+        "Relax version requirements in local manifests blocking usable versions",
+    );
+    opts.optflag(
+        "y",
+        "yes",
+        "Apply changes to local manifests without asking",
+    );
+    opts.optmulti(
+        "",
+        "patch",
+        "Patch a crate with a local path through `[patch.crates-io]`",
+        "NAME=PATH",
+    );
+    opts.optopt(
+        "",
+        "vendor-dir",
+        "Vendor usable versions of unfixable crates into DIR, and patch them",
+        "DIR",
+    );
+    opts.optflag(
+        "",
+        "interactive",
+        "Ask to approve each down fix step, and record the decisions",
+    );
+    opts.optopt(
+        "",
+        "choices",
+        "Where decisions made in interactive mode are saved (default `ruf_audit_choices.json`)",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "replay",
+        "Replay decisions recorded by interactive mode",
+        "FILE",
+    );
+    opts.optmulti(
+        "",
+        "grade",
+        "Grade of a usable ruf status, default active=info, incomplete=warning, accepted=warning",
+        "STATUS=GRADE",
+    );
+    opts.optopt(
+        "",
+        "fail-on",
+        "Fail the audit if any ruf is graded at least this (default error)",
+        "info|warning|error",
+    );
+    opts.optflag(
+        "",
+        "no-cache",
+        "Bypass the on-disk cache of database, index and filtered rufs",
+    );
+    opts.optflag("", "clear-cache", "Clear the on-disk cache before audit");
     opts.optflag("", "verbose", "Print audit detail info");
     opts.optflag("", "test", "Only used for test purpose");
 
@@ -159,6 +323,183 @@ fn main() {
         config.set_quick_fix(true);
     }
 
+    if matches.opt_present("hybrid") {
+        config.set_hybrid_fix(true);
+    }
+
+    if matches.opt_present("solver") {
+        config.set_solver_fix(true);
+    }
+
+    if let Some(toolchain) = matches.opt_str("toolchain") {
+        match get_rustc_version(&toolchain) {
+            Some(rust_version) => {
+                config.set_rust_version(rust_version);
+                // Follows the toolchain audited, rather than the default one.
+                config.set_stable_only(is_stable_toolchain(&toolchain));
+            }
+            None => {
+                error_print!(
+                    false,
+                    &format!("unknown or unsupported toolchain: {toolchain}")
+                );
+                exit(-1);
+            }
+        }
+    }
+
+    if let Some(toolchains) = matches.opt_str("toolchains") {
+        match parse_toolchains(&toolchains) {
+            Some(rust_versions) => {
+                config.set_rust_versions(rust_versions);
+                // Rufs shall be usable with every toolchain, stable ones accept fewer.
+                config.set_stable_only(toolchains.split(',').any(is_stable_toolchain));
+            }
+            None => {
+                error_print!(
+                    false,
+                    &format!("unknown or unsupported toolchains: {toolchains}")
+                );
+                exit(-1);
+            }
+        }
+    }
+
+    if matches.opt_present("stable") {
+        config.set_stable_only(true);
+    }
+
+    if let Some(rust_version) = config.get_unsupported_rustc() {
+        error_print!(
+            false,
+            &format!(
+                "rustc 1.{rust_version} of default toolchain is newer than known ruf lifetimes, choose an older one with `--toolchain`"
+            )
+        );
+        exit(-1);
+    }
+
+    if let Some(policy) = matches.opt_str("candidate-policy") {
+        let policy = match policy.as_str() {
+            "downgrade" => CandidatePolicy::PreferDowngrade,
+            "upgrade" => CandidatePolicy::PreferUpgrade,
+            "closest" => CandidatePolicy::Closest,
+            _ => {
+                error_print!(false, &format!("unknown candidate policy: {policy}"));
+                exit(-1);
+            }
+        };
+        config.set_candidate_policy(policy);
+    }
+
+    if let Some(depth) = matches.opt_str("max-depth") {
+        match depth.parse::<usize>() {
+            Ok(depth) if depth > 0 => config.set_up_fix_depth(depth),
+            _ => {
+                error_print!(false, &format!("invalid max depth: {depth}"));
+                exit(-1);
+            }
+        }
+    }
+
+    if let Some(budget) = matches.opt_str("time-budget") {
+        match budget.parse::<u64>() {
+            Ok(budget) => config.set_up_fix_budget(budget),
+            Err(_) => {
+                error_print!(false, &format!("invalid time budget: {budget}"));
+                exit(-1);
+            }
+        }
+    }
+
+    if matches.opt_present("dry-run") {
+        config.set_dry_run(true);
+    }
+
+    if matches.opt_present("write-toolchain") {
+        config.set_write_toolchain(true);
+    }
+
+    if matches.opt_present("verify") {
+        config.set_verify(true);
+    }
+
+    if matches.opt_present("disable-features") {
+        config.set_feature_fix(true);
+    }
+
+    if matches.opt_present("relax-reqs") {
+        config.set_relax_reqs(true);
+    }
+
+    if matches.opt_present("yes") {
+        config.set_assume_yes(true);
+    }
+
+    for patch in matches.opt_strs("patch") {
+        match patch.split_once('=') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+                config.add_patch(name.to_string(), path.to_string())
+            }
+            _ => {
+                error_print!(false, &format!("invalid patch: {patch}, expect NAME=PATH"));
+                exit(-1);
+            }
+        }
+    }
+
+    if let Some(vendor_dir) = matches.opt_str("vendor-dir") {
+        config.set_vendor_dir(vendor_dir);
+    }
+
+    if matches.opt_present("interactive") {
+        config.set_interactive(true);
+    }
+
+    if let Some(choices_file) = matches.opt_str("choices") {
+        config.set_choices_file(choices_file);
+    }
+
+    if let Some(replay_file) = matches.opt_str("replay") {
+        config.set_replay_file(replay_file);
+    }
+
+    for grade in matches.opt_strs("grade") {
+        let graded = match grade.split_once('=') {
+            Some((status, g)) => {
+                Grade::parse(g).is_some_and(|g| config.set_status_grade(status, g))
+            }
+            None => false,
+        };
+        if !graded {
+            error_print!(
+                false,
+                &format!(
+                    "invalid grade: {grade}, expect active|incomplete|accepted=info|warning|error"
+                )
+            );
+            exit(-1);
+        }
+    }
+
+    if let Some(fail_grade) = matches.opt_str("fail-on") {
+        match Grade::parse(&fail_grade) {
+            Some(fail_grade) => config.set_fail_grade(fail_grade),
+            None => {
+                error_print!(false, &format!("invalid grade: {fail_grade}"));
+                exit(-1);
+            }
+        }
+    }
+
+    if matches.opt_present("no-cache") {
+        config.set_cache(false);
+    }
+
+    if matches.opt_present("clear-cache") {
+        config.set_clear_cache(true);
+    }
+
     if matches.opt_present("verbose") {
         config.set_verbose(true);
     }
@@ -220,4 +561,4 @@ macro_rules! error_print {
             println!("{} {}", $crate::BOLD_RED.paint("error"), $msg);
         }
     };
-}
\ No newline at end of file
+}
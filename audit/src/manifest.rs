@@ -0,0 +1,133 @@
+//! This file edits dependency entries in local manifests, keeping their formats.
+//! Manifests are tracked by the running transaction before written.
+
+use std::fs;
+
+use toml_edit::{table, value, Document, Formatted, InlineTable, Item, TableLike, Value};
+
+use crate::error::AuditError;
+use crate::transaction;
+
+const DEP_TABLES: [&str; 2] = ["dependencies", "build-dependencies"];
+
+pub fn load(path: &str) -> Result<Document, AuditError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| AuditError::Unexpected(format!("cannot read `{path}`: {e}")))?;
+    content
+        .parse::<Document>()
+        .map_err(|e| AuditError::Unexpected(format!("cannot parse `{path}`: {e}")))
+}
+
+pub fn save(path: &str, doc: &Document) -> Result<(), AuditError> {
+    transaction::track(path)?;
+    fs::write(path, doc.to_string())
+        .map_err(|e| AuditError::Unexpected(format!("cannot write `{path}`: {e}")))
+}
+
+/// The dependency entry as a table, either general or target specific.
+/// `name` is either the key, or the package name of a renamed dependency.
+/// Version only entries are turned into inline tables.
+pub fn dependency_table<'d>(
+    doc: &'d mut Document,
+    name: &str,
+) -> Result<&'d mut dyn TableLike, AuditError> {
+    let item = dependency_item(doc, name)
+        .ok_or_else(|| AuditError::Functionality(format!("dependency `{name}` not found")))?;
+
+    if let Some(req) = item.as_str() {
+        let mut table = InlineTable::new();
+        table.insert("version", req.into());
+        *item = Item::Value(Value::InlineTable(table));
+    }
+    let table = item
+        .as_table_like_mut()
+        .ok_or_else(|| AuditError::Functionality(format!("dependency `{name}` is not a table")))?;
+    if table.contains_key("workspace") {
+        return Err(AuditError::Functionality(format!(
+            "dependency `{name}` is inherited from workspace"
+        )));
+    }
+
+    Ok(table)
+}
+
+fn dependency_item<'d>(doc: &'d mut Document, name: &str) -> Option<&'d mut Item> {
+    let found = DEP_TABLES.iter().find(|table| {
+        doc.get(table)
+            .map_or(false, |deps| entry_key(deps, name).is_some())
+    });
+    if let Some(table) = found {
+        return find_entry(doc.get_mut(table)?, name);
+    }
+
+    let target = doc.get_mut("target")?.as_table_like_mut()?;
+    target
+        .iter_mut()
+        .filter_map(|(_, platform)| platform.as_table_like_mut())
+        .flat_map(|platform| {
+            platform
+                .iter_mut()
+                .filter(|(table, _)| DEP_TABLES.contains(&table.get()))
+                .map(|(_, deps)| deps)
+                .collect::<Vec<_>>()
+        })
+        .find_map(|deps| find_entry(deps, name))
+}
+
+fn entry_key(deps: &Item, name: &str) -> Option<String> {
+    deps.as_table_like()?
+        .iter()
+        .find(|(key, entry)| {
+            *key == name || entry.get("package").and_then(|p| p.as_str()) == Some(name)
+        })
+        .map(|(key, _)| key.to_string())
+}
+
+fn find_entry<'d>(deps: &'d mut Item, name: &str) -> Option<&'d mut Item> {
+    let key = entry_key(deps, name)?;
+    deps.get_mut(&key)
+}
+
+/// Set the version requirement of a dependency, with its decorations, e.g. comments, kept.
+pub fn set_version(doc: &mut Document, name: &str, req: &str) -> Result<(), AuditError> {
+    let version_only = dependency_item(doc, name).map_or(false, |item| item.is_str());
+    let version = if version_only {
+        dependency_item(doc, name).expect("Fatal, dependency not found")
+    } else {
+        let table = dependency_table(doc, name)?;
+        if !table.contains_key("version") {
+            table.insert("version", value(req));
+            return Ok(());
+        }
+        table.get_mut("version").expect("Fatal, version not found")
+    };
+
+    match version.as_value_mut() {
+        Some(Value::String(origin)) => {
+            let decor = origin.decor().clone();
+            *origin = Formatted::new(req.to_string());
+            *origin.decor_mut() = decor;
+        }
+        _ => *version = value(req),
+    }
+    Ok(())
+}
+
+/// Patch a crates.io crate with a local path, in `[patch.crates-io]`.
+pub fn set_patch(doc: &mut Document, name: &str, path: &str) {
+    if !doc.contains_key("patch") {
+        let mut patch = table();
+        patch
+            .as_table_mut()
+            .expect("Fatal, patch is not a table")
+            .set_implicit(true);
+        doc["patch"] = patch;
+    }
+    if doc["patch"].get("crates-io").is_none() {
+        doc["patch"]["crates-io"] = table();
+    }
+
+    let mut entry = InlineTable::new();
+    entry.insert("path", path.into());
+    doc["patch"]["crates-io"][name] = Item::Value(Value::InlineTable(entry));
+}
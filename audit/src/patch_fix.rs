@@ -0,0 +1,206 @@
+//! This file patches unfixable crates through `[patch.crates-io]` in the workspace manifest,
+//! when no published version meets both the semver requirements and ruf usability.
+//!
+//! The patch points either at a local path given by user, or at the nearest usable version
+//! vendored into a local directory. Vendored crates keep their own versions, so the patch
+//! is only used if requirements of the parents admit it.
+
+use std::fs;
+use std::path::{Component, Path};
+use std::process::Command;
+
+use basic_usages::external::semver::Version;
+
+use tame_index::external::reqwest;
+
+use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
+use crate::error::AuditError;
+use crate::fix_plan::FixStep;
+use crate::manifest;
+use crate::transaction;
+use crate::{info_print, warn_print};
+
+/// Patch the issued crate `name@ver`, returns whether the patch is used in the dep tree.
+pub fn patch_fix(
+    config: &BuildConfig,
+    dm: &mut DepManager,
+    issued: &str,
+    queit: bool,
+) -> Result<bool, AuditError> {
+    let Some((name, ver)) = issued.rsplit_once('@') else {
+        return Ok(false);
+    };
+    // Patched crates become local ones, which are not patched again.
+    if dm.is_local(name, ver) {
+        return Ok(false);
+    }
+
+    let (path, change) = if let Some(path) = config.get_patch(name) {
+        if !Path::new(path).join("Cargo.toml").exists() {
+            warn_print!(
+                queit,
+                "\tPatching",
+                &format!("`{path}` is not a crate, cannot patch {name}")
+            );
+            return Ok(false);
+        }
+        (path.to_string(), format!("patch `{name}` with `{path}`"))
+    } else if let Some(vendor_dir) = config.get_vendor_dir() {
+        let Some(usable_ver) = nearest_usable(config, dm, name, ver, queit)? else {
+            warn_print!(
+                queit,
+                "\tPatching",
+                &format!("no usable version of {name} to vendor")
+            );
+            return Ok(false);
+        };
+        info_print!(
+            queit,
+            "\tPatching",
+            &format!("vendor {name}@{usable_ver} into `{vendor_dir}`")
+        );
+        let change = format!("patch `{name}` with {name}@{usable_ver} vendored in `{vendor_dir}`");
+        // Dry runs never write outside the scratch workspace.
+        let outside = Path::new(vendor_dir).is_absolute()
+            || Path::new(vendor_dir)
+                .components()
+                .any(|c| c == Component::ParentDir);
+        let vendor_dir = if config.is_dry_run() && outside {
+            format!("{}/vendor", dm.workspace_root())
+        } else {
+            vendor_dir.to_string()
+        };
+        (vendor(&vendor_dir, name, &usable_ver)?, change)
+    } else {
+        info_print!(
+            queit,
+            "\tSuggest",
+            &format!(
+                "patch {name} through `[patch.crates-io]`, pass `--patch {name}=PATH` or `--vendor-dir DIR`"
+            )
+        );
+        return Ok(false);
+    };
+
+    let root_manifest = dm.root_manifest();
+    let origin = dm.snapshot()?;
+    let mut doc = manifest::load(&root_manifest)?;
+    manifest::set_patch(&mut doc, name, &path);
+    manifest::save(&root_manifest, &doc)?;
+    dm.reload()?;
+
+    // Cargo ignores patches whose version is not admitted by requirements.
+    if !dm.has_local(name) {
+        warn_print!(
+            queit,
+            "\tPatching",
+            &format!(
+                "patch of {name} is not used, its version does not meet requirements of its parents"
+            )
+        );
+        dm.restore(&origin)?;
+        return Ok(false);
+    }
+
+    info_print!(
+        queit,
+        "\tFixing",
+        &format!("edit `{root_manifest}`, {change}")
+    );
+    dm.push_step(FixStep::Manifest {
+        path: root_manifest,
+        change,
+    });
+
+    Ok(true)
+}
+
+/// The usable version closest to current one, preferring those admitted by semver requirements.
+fn nearest_usable(
+    config: &BuildConfig,
+    dm: &DepManager,
+    name: &str,
+    ver: &str,
+    queit: bool,
+) -> Result<Option<Version>, AuditError> {
+    let graph = dm.graph();
+    let Some(pkgnx) = graph
+        .node_indices()
+        .find(|nx| graph[*nx].name.as_str() == name && graph[*nx].version.to_string() == ver)
+    else {
+        return Ok(None);
+    };
+
+    let versions = match dm.get_versions(pkgnx) {
+        Ok(versions) => versions,
+        Err(AuditError::Candidate(e)) => {
+            warn_print!(queit, "\tPatching", &format!("{e}"));
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let (cad_vers, cad_rufs): (Vec<_>, Vec<_>) = versions.into_iter().unzip();
+    let jobs = cad_vers
+        .iter()
+        .zip(cad_rufs)
+        .map(|(ver, rufs)| (name, ver, rufs, None))
+        .collect();
+    let mut usable_vers = vec![];
+    let filtered = config.filter_rufs_many(jobs)?;
+    for (cad, used_rufs) in cad_vers.into_iter().zip(filtered) {
+        if config.rufs_usable(&used_rufs) {
+            usable_vers.push(cad);
+        }
+    }
+
+    // No parent is excepted, as a crate is never its own parent.
+    let admitted = dm.admitted_by_others(pkgnx, pkgnx, &usable_vers)?;
+    let usable_vers = if admitted.is_empty() {
+        usable_vers
+    } else {
+        admitted
+    };
+
+    Ok(config.choose_candidate(&graph[pkgnx].version, usable_vers))
+}
+
+/// Download and unpack the crate into the vendor directory, returns the crate path.
+///
+/// Directories created are tracked by the running transaction, so rollbacks remove them.
+fn vendor(vendor_dir: &str, name: &str, usable_ver: &Version) -> Result<String, AuditError> {
+    let path = format!("{vendor_dir}/{name}-{usable_ver}");
+    if !Path::new(&path).exists() {
+        if Path::new(vendor_dir).exists() {
+            transaction::track_dir(&path)?;
+        } else {
+            transaction::track_dir(vendor_dir)?;
+        }
+        fs::create_dir_all(vendor_dir)
+            .map_err(|e| AuditError::Unexpected(format!("cannot create `{vendor_dir}`: {e}")))?;
+
+        let url = format!("https://static.crates.io/crates/{name}/{name}-{usable_ver}.crate");
+        let content = reqwest::blocking::get(&url)
+            .and_then(|resp| resp.error_for_status())
+            .and_then(|resp| resp.bytes())
+            .map_err(|e| AuditError::Unexpected(format!("cannot download `{url}`: {e}")))?;
+
+        let archive = format!("{path}.crate");
+        fs::write(&archive, content)
+            .map_err(|e| AuditError::Unexpected(format!("cannot write `{archive}`: {e}")))?;
+        let output = Command::new("tar")
+            .args(["-xzf", &archive, "-C", vendor_dir])
+            .output()
+            .map_err(|e| AuditError::Unexpected(format!("cannot run tar: {e}")))?;
+        let _ = fs::remove_file(&archive);
+        if !output.status.success() {
+            return Err(AuditError::Unexpected(format!(
+                "cannot unpack `{archive}`: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+    }
+
+    Ok(path)
+}
@@ -0,0 +1,435 @@
+//! This file explains ruf issues, i.e. why a crate is not usable under current configurations,
+//! and why we fail to fix it.
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+use basic_usages::ruf_check_info::{CondRufs, RufStatus, UsedRufs};
+use basic_usages::ruf_lifetime::{get_ruf_status, RUSTC_VER_NUM};
+use basic_usages::rustc_version::get_nightly_version;
+
+use cargo_metadata::semver::VersionReq;
+
+use crate::build_config::{BuildConfig, Grade};
+use crate::cache;
+use crate::dep_manager::DepManager;
+use crate::{error_print, info_print, warn_print};
+
+const MAX_REJECTED_SHOWN: usize = 10;
+
+/// Why a candidate version is rejected during fix.
+#[derive(Debug)]
+pub enum Rejection {
+    /// Not allowed by semver requirements.
+    Semver(String),
+    /// Uses rufs not usable in current configurations.
+    UnusableRufs(Vec<String>),
+}
+
+/// One layer of up fix, where we try changing the strict parent of `dep`.
+#[derive(Debug)]
+pub struct UpFixStep {
+    /// The crate to be fixed at this layer.
+    pub dep: String,
+    /// Its strictest parent, `None` if reaching root.
+    pub parent: Option<String>,
+    /// The requirement of the parent on `dep`, which blocks usable versions.
+    pub req: Option<VersionReq>,
+    /// Whether the parent has other versions recorded in database.
+    pub in_db: bool,
+    /// Why the parent has no database entry at all, if so.
+    pub no_db_entry: Option<String>,
+    /// Candidate versions of the parent rejected, and why.
+    pub rejected: Vec<(Version, Rejection)>,
+    /// Usable versions of `dep` admitted by its other parents, if the parent is a local crate,
+    /// so that relaxing the local requirement alone makes it fixable.
+    pub relaxable: Vec<Version>,
+}
+
+/// Print the whole up fix path tried for the issued crate, from itself up to the root.
+pub fn show_fail_path(issued: &str, path: &[UpFixStep], queit: bool) {
+    if queit {
+        return;
+    }
+
+    warn_print!(false, "\tPath", &format!("up fix path of {issued}:"));
+    for (i, step) in path.iter().enumerate() {
+        let Some(parent) = &step.parent else {
+            warn_print!(
+                false,
+                "\tPath",
+                &format!(
+                    "{}. {} has no parent to relax, reaching root",
+                    i + 1,
+                    step.dep
+                )
+            );
+            continue;
+        };
+
+        let req = step
+            .req
+            .as_ref()
+            .map_or("no requirement".to_string(), |req| format!("`{req}`"));
+        warn_print!(
+            false,
+            "\tPath",
+            &format!(
+                "{}. {} is blocked by {parent}, requiring {req}",
+                i + 1,
+                step.dep
+            )
+        );
+
+        if let Some(reason) = &step.no_db_entry {
+            warn_print!(false, "\t\tReject", &format!("no DB entry, {reason}"));
+            continue;
+        }
+        if !step.in_db {
+            warn_print!(
+                false,
+                "\t\tReject",
+                "no other versions recorded in database"
+            );
+            continue;
+        }
+        if step.rejected.is_empty() {
+            warn_print!(false, "\t\tReject", "no candidates rejected");
+        }
+        // Popular crates have hundreds of versions, we only show the newest ones.
+        for (ver, rejection) in step.rejected.iter().take(MAX_REJECTED_SHOWN) {
+            let reason = match rejection {
+                Rejection::Semver(req) => format!("semver mismatch, {req}"),
+                Rejection::UnusableRufs(rufs) => {
+                    format!("unusable rufs `{}`", rufs.join("`, `"))
+                }
+            };
+            warn_print!(false, "\t\tReject", &format!("{ver}: {reason}"));
+        }
+        if step.rejected.len() > MAX_REJECTED_SHOWN {
+            warn_print!(
+                false,
+                "\t\tReject",
+                &format!(
+                    "... and {} older versions",
+                    step.rejected.len() - MAX_REJECTED_SHOWN
+                )
+            );
+        }
+    }
+}
+
+/// Print a report for each crate failing ruf checks, with every offending ruf explained.
+pub fn explain_issues(
+    config: &BuildConfig,
+    used_rufs: &HashMap<String, UsedRufs>,
+    dm: &DepManager,
+    queit: bool,
+) {
+    if queit {
+        return;
+    }
+
+    let graph = dm.graph();
+    let mut issued: Vec<_> = used_rufs
+        .iter()
+        .filter(|(_, rufs)| !config.rufs_usable(rufs))
+        .collect();
+    issued.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (crate_name, rufs) in issued {
+        let pkgs: Vec<_> = graph
+            .node_indices()
+            .map(|nx| &graph[nx])
+            .filter(|pkg| pkg.name.as_str().replace('-', "_") == *crate_name)
+            .collect();
+        if pkgs.is_empty() {
+            warn_print!(
+                false,
+                "\tExplain",
+                &format!("`{crate_name}` uses unusable rufs, but is not found in `Cargo.lock`")
+            );
+            explain_rufs(config, rufs, None);
+        }
+
+        for pkg in pkgs {
+            let (name, ver) = (pkg.name.as_str(), pkg.version.to_string());
+            warn_print!(
+                false,
+                "\tExplain",
+                &format!("`{name}@{ver}` uses unusable rufs")
+            );
+
+            // Local crates are not recorded in database.
+            let cond_rufs = if dm.is_local(name, &ver) {
+                None
+            } else {
+                cache::get_rufs_with_crate_name(name)
+                    .ok()
+                    .and_then(|mut rufs| rufs.remove(&pkg.version))
+            };
+            explain_rufs(config, rufs, cond_rufs.as_ref());
+        }
+    }
+}
+
+fn explain_rufs(config: &BuildConfig, rufs: &UsedRufs, cond_rufs: Option<&CondRufs>) {
+    for ruf in rufs.iter() {
+        // The first rustc it is not usable with, in multi-toolchain mode.
+        let Some((rust_version, status)) = config
+            .get_rust_versions()
+            .iter()
+            .map(|rust_version| (*rust_version, get_ruf_status(ruf, *rust_version)))
+            .find(|(_, status)| !config.status_usable(status))
+        else {
+            continue;
+        };
+
+        let usable_rustc = config.usable_rustc_for_ruf(ruf);
+        let usable = match usable_ranges(&usable_rustc).as_slice() {
+            [] => "usable in no rustc".to_string(),
+            ranges => format!("usable in rustc {}", ranges.join(", ")),
+        };
+        let last = match usable_rustc.iter().max() {
+            Some(last) if *last + 1 >= RUSTC_VER_NUM as u32 => "still usable".to_string(),
+            Some(last) if config.is_stable_only() => format!("last usable in rustc 1.{last}.*"),
+            // The nightly just before the next rustc, like in `nightly_range`.
+            Some(last) => match nightly_before(get_nightly_version(*last + 1)) {
+                Some(nightly) => format!("last usable in {nightly}, rustc 1.{last}.*"),
+                None => format!("last usable in rustc 1.{last}.*"),
+            },
+            None => "never usable".to_string(),
+        };
+
+        info_print!(
+            false,
+            "\t\tRuf",
+            &format!(
+                "`{ruf}` is {status} in rustc 1.{rust_version}.*, {usable}, {last}, from {}",
+                ruf_source(ruf, cond_rufs)
+            )
+        );
+    }
+}
+
+/// Report graded rufs of each crate, returns the worst grade, or `None` if no rufs used.
+pub fn grade_rufs(
+    config: &BuildConfig,
+    used_rufs: &HashMap<String, UsedRufs>,
+    queit: bool,
+) -> Option<Grade> {
+    let mut crates: Vec<_> = used_rufs.iter().collect();
+    crates.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut worst = None;
+    for (crate_name, rufs) in crates {
+        let graded: Vec<_> = rufs
+            .iter()
+            .map(|ruf| (ruf, config.grade_ruf(ruf)))
+            .collect();
+        let Some(grade) = graded.iter().map(|(_, (grade, _, _))| *grade).max() else {
+            continue;
+        };
+        worst = worst.max(Some(grade));
+
+        let msg = format!("`{crate_name}` uses {} rufs, graded {grade}", graded.len());
+        match grade {
+            Grade::Error => error_print!(queit, &msg),
+            Grade::Warning => warn_print!(queit, "\tGrade", &msg),
+            Grade::Info => info_print!(queit, "\tGrade", &msg),
+        }
+        for (ruf, (grade, status, rust_version)) in graded {
+            if grade == Grade::Info {
+                continue;
+            }
+            let reason = match status {
+                RufStatus::Accepted => "its `#![feature]` is no longer needed",
+                RufStatus::Incomplete => "it is known to be incomplete, and may be unsound",
+                _ => "it is not usable",
+            };
+            info_print!(
+                queit,
+                "\t\tRuf",
+                &format!(
+                    "`{ruf}` is {status} in rustc 1.{rust_version}.*, {reason}, graded {grade}"
+                )
+            );
+        }
+    }
+
+    worst
+}
+
+/// Contiguous usable rustc versions, e.g. `1.20-1.40`.
+fn usable_ranges(usable_rustc: &HashSet<u32>) -> Vec<String> {
+    contiguous(usable_rustc.iter().cloned())
+        .into_iter()
+        .map(|(start, end)| rustc_range(start, end))
+        .collect()
+}
+
+/// Report usable rustc of current dep tree as contiguous nightly ranges,
+/// and the crates and rufs ruling out each rustc not usable.
+pub fn explain_rustc(config: &BuildConfig, used_rufs: &HashMap<String, UsedRufs>, queit: bool) {
+    if queit {
+        return;
+    }
+
+    let mut crates: Vec<_> = used_rufs.iter().collect();
+    crates.sort_by(|a, b| a.0.cmp(b.0));
+    // crates and their rufs not usable in each rustc, empty if usable.
+    let blames: Vec<Vec<(&str, Vec<&str>)>> = (0..RUSTC_VER_NUM as u32)
+        .map(|rustc_version| {
+            crates
+                .iter()
+                .filter_map(|(crate_name, rufs)| {
+                    let unusable: Vec<&str> = rufs
+                        .iter()
+                        .filter(|ruf| !config.status_usable(&get_ruf_status(ruf, rustc_version)))
+                        .map(|ruf| ruf.as_str())
+                        .collect();
+                    (!unusable.is_empty()).then_some((crate_name.as_str(), unusable))
+                })
+                .collect()
+        })
+        .collect();
+
+    let usable = contiguous(
+        (0..RUSTC_VER_NUM as u32)
+            .filter(|rustc_version| blames[*rustc_version as usize].is_empty()),
+    );
+    if usable.is_empty() {
+        warn_print!(
+            false,
+            "\tRustc",
+            "no rustc can be used with current dep tree"
+        );
+    }
+    for (start, end) in usable {
+        info_print!(
+            false,
+            "\tRustc",
+            &format!("usable in {}", nightly_range(config, start, end))
+        );
+    }
+
+    // Neighboring rustc ruled out by the same crates and rufs are reported together.
+    let mut excluded: Vec<(u32, u32)> = vec![];
+    for rustc_version in 0..RUSTC_VER_NUM as u32 {
+        let blame = &blames[rustc_version as usize];
+        if blame.is_empty() {
+            continue;
+        }
+        match excluded.last_mut() {
+            Some((_, end)) if *end + 1 == rustc_version && blames[*end as usize] == *blame => {
+                *end = rustc_version
+            }
+            _ => excluded.push((rustc_version, rustc_version)),
+        }
+    }
+    for (start, end) in excluded {
+        warn_print!(
+            false,
+            "\tRustc",
+            &format!(
+                "not usable in {}, ruled out by",
+                nightly_range(config, start, end)
+            )
+        );
+        for (crate_name, rufs) in &blames[start as usize] {
+            let rufs: Vec<String> = rufs.iter().map(|ruf| format!("`{ruf}`")).collect();
+            info_print!(
+                false,
+                "\t\tCrate",
+                &format!("`{crate_name}` uses {}", rufs.join(", "))
+            );
+        }
+    }
+}
+
+/// Contiguous ranges of rustc versions.
+fn contiguous(rustc_versions: impl Iterator<Item = u32>) -> Vec<(u32, u32)> {
+    let mut vers: Vec<u32> = rustc_versions.collect();
+    vers.sort_unstable();
+
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for ver in vers {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == ver => *end = ver,
+            _ => ranges.push((ver, ver)),
+        }
+    }
+
+    ranges
+}
+
+fn rustc_range(start: u32, end: u32) -> String {
+    if start == end {
+        format!("1.{start}")
+    } else {
+        format!("1.{start}-1.{end}")
+    }
+}
+
+/// Nightly dates of a rustc range, a nightly belongs to the latest rustc released before it.
+/// Only rustc versions are shown if stable only.
+fn nightly_range(config: &BuildConfig, start: u32, end: u32) -> String {
+    let rustc = rustc_range(start, end);
+    if config.is_stable_only() {
+        return format!("rustc {rustc}");
+    }
+
+    let since = get_nightly_version(start);
+    if end + 1 < RUSTC_VER_NUM as u32 {
+        format!(
+            "{since} up to {} (exclusive), rustc {rustc}",
+            get_nightly_version(end + 1)
+        )
+    } else {
+        format!("{since} onwards, rustc {rustc}")
+    }
+}
+
+/// The nightly a day before given one, e.g. `nightly-2023-02-28` before `nightly-2023-03-01`.
+fn nightly_before(nightly: &str) -> Option<String> {
+    let mut date = nightly
+        .strip_prefix("nightly-")?
+        .split('-')
+        .map(|part| part.parse::<u32>().ok());
+    let (mut year, mut month, mut day) = (date.next()??, date.next()??, date.next()??);
+
+    if day > 1 {
+        day -= 1;
+    } else {
+        (year, month) = if month > 1 {
+            (year, month - 1)
+        } else {
+            (year - 1, 12)
+        };
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        day = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+    }
+
+    Some(format!("nightly-{year:04}-{month:02}-{day:02}"))
+}
+
+/// Where the ruf is enabled, found from the rufs recorded in database.
+fn ruf_source(ruf: &str, cond_rufs: Option<&CondRufs>) -> String {
+    let Some(cond_rufs) = cond_rufs else {
+        return "unknown source".to_string();
+    };
+
+    let mut conds = cond_rufs.iter().filter(|cond_ruf| cond_ruf.feature == ruf);
+    if conds.clone().any(|cond_ruf| cond_ruf.cond.is_none()) {
+        return "unconditional `#![feature]`".to_string();
+    }
+
+    match conds.find_map(|cond_ruf| cond_ruf.cond.as_ref()) {
+        Some(cond) => format!("`#![cfg_attr({cond}, feature({ruf}))]`"),
+        None => "unknown source".to_string(),
+    }
+}
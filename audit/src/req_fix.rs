@@ -0,0 +1,135 @@
+//! This file relaxes version requirements in local manifests, when a local crate
+//! is the only one blocking usable versions of its dependency, e.g. `midi = "=0.0.2"`.
+//!
+//! Local crates cannot be changed by up fix, but their requirements can, with confirmation.
+
+use std::io::{self, BufRead, Write};
+
+use basic_usages::external::semver::Version;
+
+use cargo_metadata::semver::{Op, VersionReq};
+
+use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
+use crate::error::AuditError;
+use crate::fix_plan::FixStep;
+use crate::manifest;
+use crate::report::UpFixStep;
+use crate::{info_print, warn_print};
+
+/// Try relaxing the local requirement blocking the up fix `path`, returns whether it is relaxed.
+pub fn relax_local_req(
+    config: &BuildConfig,
+    dm: &mut DepManager,
+    path: &[UpFixStep],
+    queit: bool,
+) -> Result<bool, AuditError> {
+    // The lowest blocking layer needs the least changes.
+    let Some(step) = path.iter().find(|step| !step.relaxable.is_empty()) else {
+        return Ok(false);
+    };
+    let (Some(parent), Some(req)) = (&step.parent, &step.req) else {
+        return Ok(false);
+    };
+    let (Some((p_name, p_ver)), Some((dep_name, dep_ver))) =
+        (parent.rsplit_once('@'), step.dep.rsplit_once('@'))
+    else {
+        return Ok(false);
+    };
+    let Some(manifest) = dm
+        .local_manifest(p_name, p_ver)
+        .map(|path| path.to_string())
+    else {
+        return Ok(false);
+    };
+    // The tree may be changed by a partial up fix.
+    let graph = dm.graph();
+    if !graph
+        .node_indices()
+        .any(|nx| graph[nx].name.as_str() == dep_name && graph[nx].version.to_string() == dep_ver)
+    {
+        return Ok(false);
+    }
+
+    let cur_ver = Version::parse(dep_ver)
+        .map_err(|e| AuditError::Unexpected(format!("cannot parse version `{dep_ver}`: {e}")))?;
+    let origin = dm.snapshot()?;
+    let mut candidates = step.relaxable.clone();
+    // Updates may still fail, e.g. blocked by other crates, then we try the next one.
+    while let Some(fix_ver) = config.choose_candidate(&cur_ver, candidates.clone()) {
+        candidates.retain(|ver| *ver != fix_ver);
+        let new_req = relaxed_req(req, &fix_ver);
+
+        info_print!(
+            queit,
+            "\tSuggest",
+            &format!(
+                "relax requirement of {parent} on {dep_name} from `{req}` to `{new_req}` in `{manifest}`, \
+                which is the only one blocking usable versions"
+            )
+        );
+        if !config.is_relax_reqs() {
+            info_print!(
+                queit,
+                "\tSuggest",
+                "pass `--relax-reqs` to apply it to local manifests"
+            );
+            return Ok(false);
+        }
+        if !confirm(config, "apply the change?") {
+            warn_print!(queit, "\tSkipped", "requirement not relaxed");
+            return Ok(false);
+        }
+
+        let mut doc = manifest::load(&manifest)?;
+        manifest::set_version(&mut doc, dep_name, &new_req)?;
+        manifest::save(&manifest, &doc)?;
+        match dm.update_pkg(dep_name, dep_ver, &fix_ver.to_string()) {
+            Ok(()) => {}
+            Err(AuditError::Candidate(e)) => {
+                warn_print!(queit, "\tSkipped", &format!("{e}, requirement restored"));
+                dm.restore(&origin)?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+
+        let change = format!("relax requirement on `{dep_name}` from `{req}` to `{new_req}`");
+        info_print!(queit, "\tFixing", &format!("edit `{manifest}`, {change}"));
+        dm.push_step(FixStep::Manifest {
+            path: manifest,
+            change,
+        });
+        dm.reload()?;
+
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// The least changed requirement admitting the version, keeping exact and tilde ones as they are.
+fn relaxed_req(req: &VersionReq, ver: &Version) -> String {
+    match req.comparators.as_slice() {
+        [cmp] if cmp.op == Op::Exact => format!("={ver}"),
+        [cmp] if cmp.op == Op::Tilde => format!("~{ver}"),
+        _ => ver.to_string(),
+    }
+}
+
+/// Ask user for confirmation on stdin, unless `--yes` is given.
+pub fn confirm(config: &BuildConfig, question: &str) -> bool {
+    if config.is_assume_yes() {
+        return true;
+    }
+
+    // Always asked, or we wait silently in quiet mode.
+    info_print!(false, "\tConfirm", &format!("{question} [y/N]"));
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
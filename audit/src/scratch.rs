@@ -0,0 +1,251 @@
+//! This file sets up scratch workspaces for dry runs.
+//!
+//! A dry run works on a copy of current workspace, so `Cargo.lock`, manifests and other
+//! files of the user are never touched, not even restored afterwards.
+//! Path dependencies outside the workspace are copied one by one, and manifests pointing
+//! to them are rewritten to their copies.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cargo_metadata::MetadataCommand;
+use toml_edit::{Item, Table, TableLike, Value};
+
+use crate::error::AuditError;
+use crate::manifest;
+use crate::{warn_print, RUSTV};
+
+/// A copy of current workspace, which is current directory until dropped.
+pub struct Scratch {
+    /// where we come from.
+    origin_dir: PathBuf,
+    /// the copy, removed on drop, each run has its own.
+    dir: PathBuf,
+}
+
+/// Directories copied, as `(origin, copy)`, both canonicalized.
+struct Copies {
+    dir: PathBuf,
+    copies: Vec<(PathBuf, PathBuf)>,
+    /// manifests copied, as `(origin, copy)`, whose paths are still to be rewritten.
+    manifests: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Scratch {
+    /// Copy current workspace, along with local path dependencies, and enter the copy.
+    pub fn enter() -> Result<Self, AuditError> {
+        let origin_dir = env::current_dir()
+            .map_err(|e| AuditError::Unexpected(format!("cannot get current dir: {e}")))?;
+
+        // No deps, so that cargo does not resolve, nor write `Cargo.lock`.
+        let metadata = MetadataCommand::new()
+            .env("RUSTUP_TOOLCHAIN", RUSTV)
+            .no_deps()
+            .exec()
+            .map_err(|e| AuditError::Unexpected(format!("cannot load metadata: {e}")))?;
+        let workspace_root = metadata.workspace_root.into_std_path_buf();
+        let target_dir = metadata.target_directory.into_std_path_buf();
+
+        let dir = unique_dir()
+            .map_err(|e| AuditError::Unexpected(format!("cannot create scratch dir: {e}")))?;
+        let scratch = Self {
+            origin_dir: origin_dir.clone(),
+            dir: dir.clone(),
+        };
+
+        let mut copies = Copies {
+            dir: dir.clone(),
+            copies: vec![],
+            manifests: vec![],
+        };
+        let workspace = copies
+            .copy(&workspace_root, &dir.join("workspace"), &target_dir)
+            .map_err(|e| {
+                AuditError::Unexpected(format!(
+                    "cannot copy `{}` to `{}`: {e}",
+                    workspace_root.display(),
+                    dir.display()
+                ))
+            })?;
+        copies.rewrite_manifests()?;
+
+        // Started outside the workspace, e.g. in a path dependency, we take the workspace root.
+        let cur_dir = match origin_dir.canonicalize() {
+            Ok(origin_dir) => copies.map(&origin_dir).unwrap_or(workspace),
+            Err(_) => workspace,
+        };
+        env::set_current_dir(&cur_dir).map_err(|e| {
+            AuditError::Unexpected(format!("cannot enter `{}`: {e}", cur_dir.display()))
+        })?;
+
+        Ok(scratch)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        if let Err(e) = env::set_current_dir(&self.origin_dir) {
+            warn_print!(
+                false,
+                "Scratch",
+                &format!("cannot leave scratch workspace: {e}")
+            );
+            return;
+        }
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            warn_print!(
+                false,
+                "Scratch",
+                &format!("cannot remove `{}`: {e}", self.dir.display())
+            );
+        }
+    }
+}
+
+impl Copies {
+    /// Copy a directory, skipping `target` and `.git`, returns where the copy lies.
+    fn copy(&mut self, from: &Path, to: &Path, target_dir: &Path) -> io::Result<PathBuf> {
+        let from = from.canonicalize()?;
+        let skip = [target_dir.to_path_buf(), from.join(".git")];
+        copy_dir(&from, to, &skip, &mut self.manifests)?;
+
+        let to = to.canonicalize()?;
+        self.copies.push((from, to.clone()));
+        Ok(to)
+    }
+
+    /// Where an origin path lies in the copies, if copied.
+    fn map(&self, origin: &Path) -> Option<PathBuf> {
+        self.copies
+            .iter()
+            .find_map(|(from, to)| origin.strip_prefix(from).ok().map(|rest| to.join(rest)))
+    }
+
+    /// Point paths in copied manifests to the copies, copying path dependencies not copied yet.
+    fn rewrite_manifests(&mut self) -> Result<(), AuditError> {
+        while let Some((origin, copy)) = self.manifests.pop() {
+            let path = copy.to_string_lossy().to_string();
+            let mut doc = manifest::load(&path)?;
+            let origin_dir = origin.parent().expect("Fatal, manifest has no parent");
+            let copy_dir = copy.parent().expect("Fatal, manifest has no parent");
+
+            let mut changed = false;
+            for entry in path_entries(doc.as_table_mut()) {
+                let Some(rel) = entry.as_str().map(PathBuf::from) else {
+                    continue;
+                };
+                // Missing paths are left to cargo to complain.
+                let Ok(target) = origin_dir.join(&rel).canonicalize() else {
+                    continue;
+                };
+
+                let mapped = match self.map(&target) {
+                    Some(mapped) => mapped,
+                    None if target.join("Cargo.toml").is_file() => {
+                        let name = target
+                            .file_name()
+                            .map_or("dep".to_string(), |name| name.to_string_lossy().to_string());
+                        let to = self
+                            .dir
+                            .join("deps")
+                            .join(format!("{}-{name}", self.copies.len()));
+                        self.copy(&target, &to, &target.join("target"))
+                            .map_err(|e| {
+                                AuditError::Unexpected(format!(
+                                    "cannot copy path dependency `{}`: {e}",
+                                    target.display()
+                                ))
+                            })?
+                    }
+                    None => continue,
+                };
+                if copy_dir.join(&rel).canonicalize().ok().as_ref() != Some(&mapped) {
+                    *entry = Value::from(mapped.to_string_lossy().to_string());
+                    changed = true;
+                }
+            }
+
+            if changed {
+                fs::write(&copy, doc.to_string()).map_err(|e| {
+                    AuditError::Unexpected(format!("cannot write `{}`: {e}", copy.display()))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Values of all `path` keys in a manifest, e.g. path dependencies and patches.
+fn path_entries(table: &mut dyn TableLike) -> Vec<&mut Value> {
+    let mut entries = vec![];
+    for (key, item) in table.iter_mut() {
+        match item {
+            Item::Value(Value::String(_)) if key.get() == "path" => {
+                entries.push(item.as_value_mut().expect("Fatal, path is not a value"));
+            }
+            Item::Value(Value::InlineTable(inline)) => entries.extend(path_entries(inline)),
+            Item::Table(table) => entries.extend(path_entries(table)),
+            Item::ArrayOfTables(tables) => entries.extend(
+                tables
+                    .iter_mut()
+                    .flat_map(|table: &mut Table| path_entries(table)),
+            ),
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+/// A new directory for this run only, so that concurrent dry runs do not share it.
+fn unique_dir() -> io::Result<PathBuf> {
+    let base = env::temp_dir().join("ruf_audit");
+    fs::create_dir_all(&base)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    for i in 0.. {
+        let dir = base.join(format!("{}-{nanos}-{i}", process::id()));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!()
+}
+
+fn copy_dir(
+    from: &Path,
+    to: &Path,
+    skip: &[PathBuf],
+    manifests: &mut Vec<(PathBuf, PathBuf)>,
+) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+        if skip.contains(&path) {
+            continue;
+        }
+
+        // Symlinks are followed, the copy shall build the same.
+        let file_type = fs::metadata(&path)?.file_type();
+        let copy = to.join(entry.file_name());
+        if file_type.is_dir() {
+            copy_dir(&path, &copy, skip, manifests)?;
+        } else if file_type.is_file() {
+            fs::copy(&path, &copy)?;
+            if entry.file_name() == "Cargo.toml" {
+                manifests.push((path, copy));
+            }
+        }
+    }
+
+    Ok(())
+}
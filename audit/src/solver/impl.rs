@@ -0,0 +1,320 @@
+use std::time::{Duration, Instant};
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+use basic_usages::ruf_check_info::UsedRufs;
+
+use cargo_lock::dependency::graph::{EdgeDirection, NodeIndex};
+use cargo_metadata::semver::VersionReq;
+use petgraph::visit::{self, EdgeRef};
+
+use super::{Domain, Solver};
+use crate::build_config::BuildConfig;
+use crate::cache;
+use crate::dep_manager::DepManager;
+use crate::error::AuditError;
+
+impl<'a, 'c, 'd> Solver<'a, 'c, 'd> {
+    pub fn new(
+        config: &'a BuildConfig<'c>,
+        dm: &'a DepManager<'d>,
+        used_rufs: &'a HashMap<String, UsedRufs>,
+    ) -> Self {
+        let graph = dm.graph();
+
+        // Parents first, so that a package is checked against its dependents' choices.
+        // Lockfiles with cycles (e.g. local dev-dependencies) fall back to bfs order.
+        let order = petgraph::algo::toposort(graph, None).unwrap_or_else(|_| {
+            let mut order = vec![];
+            let mut bfs = visit::Bfs::new(graph, dm.root());
+            while let Some(nx) = bfs.next(graph) {
+                order.push(nx);
+            }
+            order
+        });
+        let pos = order.iter().enumerate().map(|(i, nx)| (*nx, i)).collect();
+
+        Self {
+            config,
+            dm,
+            used_rufs,
+            deadline: Instant::now() + Duration::from_secs(config.get_up_fix_budget()),
+            order,
+            pos,
+            db_rufs: HashMap::default(),
+            usable: HashMap::default(),
+            reqs: HashMap::default(),
+        }
+    }
+
+    /// Search for a ruf-usable dependency tree.
+    ///
+    /// Returns the packages to be changed, as `(name, current version, new version)`, parents first.
+    /// If no such tree exists within current graph, or the time budget runs out,
+    /// a functionality error is returned.
+    pub fn solve(mut self) -> Result<Vec<(String, Version, Version)>, AuditError> {
+        let num = self.order.len();
+        let mut domains: Vec<Option<Domain>> = (0..num).map(|_| None).collect();
+        let mut assigned: HashMap<NodeIndex, Version> = HashMap::default();
+
+        let mut i = 0;
+        while i < num {
+            if Instant::now() > self.deadline {
+                return Err(AuditError::Functionality(
+                    "solver runs out of time budget".to_string(),
+                ));
+            }
+
+            let nx = self.order[i];
+            let domain = domains[i].get_or_insert_with(|| Domain {
+                vers: vec![self.dm.graph()[nx].version.clone()],
+                expanded: false,
+                conflicts: HashSet::default(),
+            });
+
+            if let Some(ver) = self.next_version(nx, domain, &assigned)? {
+                assigned.insert(nx, ver);
+                i += 1;
+                continue;
+            }
+
+            // No version left, we jump back to the latest package in conflict.
+            let conflicts = std::mem::take(&mut domain.conflicts);
+            let Some(back) = conflicts.iter().max().cloned() else {
+                // Nothing to blame, this proves no usable tree exists with the packages we have.
+                let pkg = &self.dm.graph()[nx];
+                return Err(AuditError::Functionality(format!(
+                    "no ruf usable tree within the current graph, no version of {} fits its dependents",
+                    pkg.name
+                )));
+            };
+
+            for (domain, nx) in domains[back + 1..=i]
+                .iter_mut()
+                .zip(&self.order[back + 1..=i])
+            {
+                *domain = None;
+                assigned.remove(nx);
+            }
+            assigned.remove(&self.order[back]);
+            domains[back]
+                .as_mut()
+                .expect("Fatal, jump back to unvisited package")
+                .conflicts
+                .extend(conflicts.into_iter().filter(|c| *c != back));
+            i = back;
+        }
+
+        let graph = self.dm.graph();
+        Ok(self
+            .order
+            .iter()
+            .filter_map(|nx| {
+                let pkg = &graph[*nx];
+                let ver = &assigned[nx];
+                (*ver != pkg.version)
+                    .then(|| (pkg.name.to_string(), pkg.version.clone(), ver.clone()))
+            })
+            .collect())
+    }
+
+    /// Pop the next version in domain which is consistent with assigned packages.
+    fn next_version(
+        &mut self,
+        nx: NodeIndex,
+        domain: &mut Domain,
+        assigned: &HashMap<NodeIndex, Version>,
+    ) -> Result<Option<Version>, AuditError> {
+        let dm = self.dm;
+        let graph = dm.graph();
+        let pkg = &graph[nx];
+
+        loop {
+            let ver = match domain.vers.pop() {
+                Some(ver) => ver,
+                None if !domain.expanded => {
+                    domain.expanded = true;
+                    domain.vers = self.alternatives(nx)?;
+                    continue;
+                }
+                None => return Ok(None),
+            };
+
+            // semver reqs from assigned dependents and to assigned dependencies.
+            let mut consistent = true;
+            for edge in graph.edges_directed(nx, EdgeDirection::Incoming) {
+                let p = edge.source();
+                if let Some(p_ver) = assigned.get(&p) {
+                    let p_pkg = &graph[p];
+                    if *p_ver == p_pkg.version && ver == pkg.version {
+                        // already consistent in lockfile.
+                        continue;
+                    }
+                    if !self.req_matches(p_pkg.name.as_str(), p_ver, pkg.name.as_str(), &ver)? {
+                        domain.conflicts.insert(self.pos[&p]);
+                        consistent = false;
+                    }
+                }
+            }
+            for edge in graph.edges_directed(nx, EdgeDirection::Outgoing) {
+                let c = edge.target();
+                if let Some(c_ver) = assigned.get(&c) {
+                    let c_pkg = &graph[c];
+                    if *c_ver == c_pkg.version && ver == pkg.version {
+                        continue;
+                    }
+                    if !self.req_matches(pkg.name.as_str(), &ver, c_pkg.name.as_str(), c_ver)? {
+                        domain.conflicts.insert(self.pos[&c]);
+                        consistent = false;
+                    }
+                }
+            }
+            if !consistent {
+                continue;
+            }
+
+            // ruf usability, only matters if some dependents still require this package.
+            if !self.is_usable(nx, &ver)? && self.is_required(nx, assigned)? {
+                for edge in graph.edges_directed(nx, EdgeDirection::Incoming) {
+                    if assigned.contains_key(&edge.source()) {
+                        domain.conflicts.insert(self.pos[&edge.source()]);
+                    }
+                }
+                continue;
+            }
+
+            return Ok(Some(ver));
+        }
+    }
+
+    /// Versions other than the locked one, the preferred one lies at the end.
+    fn alternatives(&mut self, nx: NodeIndex) -> Result<Vec<Version>, AuditError> {
+        let dm = self.dm;
+        let pkg = &dm.graph()[nx];
+        if self
+            .dm
+            .is_local(pkg.name.as_str(), &pkg.version.to_string())
+        {
+            return Ok(vec![]);
+        }
+
+        let name = pkg.name.to_string();
+        if !self.db_rufs.contains_key(&name) {
+            // crates not recorded in database have no candidates.
+            let rufs = cache::get_rufs_with_crate_name(&name).ok();
+            self.db_rufs.insert(name.clone(), rufs);
+        }
+
+        // Ranked the same as dep tree fix, the preferred one is popped first.
+        let mut vers: Vec<Version> = self.db_rufs[&name]
+            .as_ref()
+            .map(|rufs| {
+                rufs.keys()
+                    .filter(|ver| *ver != &pkg.version)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.config.rank_candidates(&pkg.version, &mut vers);
+        vers.reverse();
+
+        Ok(vers)
+    }
+
+    fn is_usable(&mut self, nx: NodeIndex, ver: &Version) -> Result<bool, AuditError> {
+        let dm = self.dm;
+        let pkg = &dm.graph()[nx];
+        let name = pkg.name.to_string();
+
+        // Crates not built in current configurations, e.g. platform specific ones, are not our concern.
+        if !self.config.is_built(&name) {
+            return Ok(true);
+        }
+
+        if *ver == pkg.version {
+            return Ok(self
+                .used_rufs
+                .get(&name.replace('-', "_"))
+                .map_or(true, |rufs| self.config.rufs_usable(rufs)));
+        }
+
+        let key = (name, ver.clone());
+        if let Some(usable) = self.usable.get(&key) {
+            return Ok(*usable);
+        }
+
+        let cond_rufs = self
+            .db_rufs
+            .get(&key.0)
+            .and_then(|rufs| rufs.as_ref())
+            .and_then(|rufs| rufs.get(ver).cloned())
+            .expect("Fatal, candidate version not found in database");
+        let used_rufs = self.config.filter_rufs(&key.0, ver, cond_rufs)?;
+        let usable = self.config.rufs_usable(&used_rufs);
+        self.usable.insert(key, usable);
+
+        Ok(usable)
+    }
+
+    /// Whether any dependent still depends on this package, with its assigned (or locked) version.
+    fn is_required(
+        &mut self,
+        nx: NodeIndex,
+        assigned: &HashMap<NodeIndex, Version>,
+    ) -> Result<bool, AuditError> {
+        let dm = self.dm;
+        let graph = dm.graph();
+        let pkg = &graph[nx];
+
+        let mut parents = graph.edges_directed(nx, EdgeDirection::Incoming).peekable();
+        if parents.peek().is_none() {
+            // root is always required.
+            return Ok(true);
+        }
+
+        for edge in parents {
+            let p_pkg = &graph[edge.source()];
+            let p_ver = assigned.get(&edge.source()).unwrap_or(&p_pkg.version);
+            if !self
+                .reqs_on(p_pkg.name.as_str(), p_ver, pkg.name.as_str())?
+                .is_empty()
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Whether `name@ver` allows `dep_ver` of its dependency `dep_name`.
+    fn req_matches(
+        &mut self,
+        name: &str,
+        ver: &Version,
+        dep_name: &str,
+        dep_ver: &Version,
+    ) -> Result<bool, AuditError> {
+        let reqs = self.reqs_on(name, ver, dep_name)?;
+        // dep not found, possibily not used, thus ok.
+        Ok(reqs.is_empty() || reqs.iter().any(|req| req.matches(dep_ver)))
+    }
+
+    fn reqs_on(
+        &mut self,
+        name: &str,
+        ver: &Version,
+        dep_name: &str,
+    ) -> Result<Vec<VersionReq>, AuditError> {
+        let key = (name.to_string(), ver.clone());
+        if !self.reqs.contains_key(&key) {
+            let reqs = self.dm.get_package_reqs(name, &ver.to_string())?;
+            self.reqs.insert(key.clone(), reqs);
+        }
+
+        Ok(self.reqs[&key]
+            .iter()
+            .filter(|(name, _)| name == dep_name)
+            .map(|(_, req)| req.clone())
+            .collect())
+    }
+}
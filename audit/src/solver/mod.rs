@@ -0,0 +1,51 @@
+//! This module resolves a ruf-usable dependency tree as a whole, rather than fixing crates one by one.
+//!
+//! Each package in the dependency tree is a variable, its domain is the versions recorded in our database,
+//! and semver reqs between dependents and dependencies are the constraints. We search with backjumping,
+//! so either a usable assignment is found, or the search proves that no such tree exists within current graph,
+//! i.e. without adding or dropping packages. The search gives up once the time budget runs out.
+
+use std::time::Instant;
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+use basic_usages::ruf_check_info::{CondRufs, UsedRufs};
+
+use cargo_lock::dependency::graph::NodeIndex;
+use cargo_metadata::semver::VersionReq;
+
+use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
+
+mod r#impl;
+
+pub struct Solver<'a, 'c, 'd> {
+    config: &'a BuildConfig<'c>,
+    dm: &'a DepManager<'d>,
+    /// rufs used by current dependency tree.
+    used_rufs: &'a HashMap<String, UsedRufs>,
+    /// the search gives up after this.
+    deadline: Instant,
+
+    /// packages to be assigned, parents come before children if possible.
+    order: Vec<NodeIndex>,
+    /// position of each package in `order`.
+    pos: HashMap<NodeIndex, usize>,
+
+    /// candidate versions and their rufs from database, `None` if the crate is not recorded.
+    db_rufs: HashMap<String, Option<HashMap<Version, CondRufs>>>,
+    /// usability of checked crate versions.
+    usable: HashMap<(String, Version), bool>,
+    /// semver reqs of crate versions on their dependencies.
+    reqs: HashMap<(String, Version), Vec<(String, VersionReq)>>,
+}
+
+/// Versions left for a package during search.
+struct Domain {
+    /// versions to try, the next one lies at the end.
+    vers: Vec<Version>,
+    /// whether versions other than the locked one are added.
+    expanded: bool,
+    /// earlier packages which rejected some of the versions.
+    conflicts: HashSet<usize>,
+}
@@ -0,0 +1,89 @@
+//! This file writes the rustc chosen by a fix into the workspace toolchain file,
+//! so that the fixed project builds with plain `cargo build`.
+
+use std::fs;
+use std::path::Path;
+
+use basic_usages::rustc_version::{get_nightly_version, get_rustc_version};
+use toml_edit::{table, value, Document};
+
+use crate::error::AuditError;
+
+const TOOLCHAIN_FILE: &str = "rust-toolchain.toml";
+const LEGACY_TOOLCHAIN_FILE: &str = "rust-toolchain";
+
+/// The toolchain channel of a rustc version, a nightly unless only stable rustc is wanted.
+pub fn toolchain_channel(rustc_version: u32, stable: bool) -> String {
+    if stable {
+        format!("1.{rustc_version}")
+    } else {
+        get_nightly_version(rustc_version).to_string()
+    }
+}
+
+/// Whether a toolchain is a stable one, e.g. `1.60`, rather than a nightly.
+pub fn is_stable_toolchain(toolchain: &str) -> bool {
+    !toolchain.trim().starts_with("nightly")
+}
+
+/// Resolve rustc versions from a list of toolchains separated by commas,
+/// each is a toolchain or an inclusive range, e.g. `nightly-2023-01-01..nightly-2023-06-01,1.60`.
+pub fn parse_toolchains(toolchains: &str) -> Option<Vec<u32>> {
+    let mut rust_versions = vec![];
+    for item in toolchains.split(',').map(|item| item.trim()) {
+        match item.split_once("..") {
+            Some((start, end)) => {
+                let (start, end) = (get_rustc_version(start)?, get_rustc_version(end)?);
+                if start > end {
+                    return None;
+                }
+                rust_versions.extend(start..=end);
+            }
+            None => rust_versions.push(get_rustc_version(item)?),
+        }
+    }
+
+    Some(rust_versions)
+}
+
+/// Write or update the toolchain file in current workspace, other settings
+/// such as components and targets are kept. Returns the file written.
+pub fn write_toolchain(channel: &str) -> Result<&'static str, AuditError> {
+    // Rustup prefers the legacy file if both exist, so we update that one.
+    let path = if Path::new(LEGACY_TOOLCHAIN_FILE).exists() {
+        LEGACY_TOOLCHAIN_FILE
+    } else {
+        TOOLCHAIN_FILE
+    };
+
+    let origin = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(AuditError::Unexpected(format!("cannot read `{path}`: {e}"))),
+    };
+
+    // Legacy file may only contain a channel name, which is replaced as a whole.
+    let mut doc = match origin.parse::<Document>() {
+        Ok(doc) if doc.get("toolchain").map_or(true, |item| item.is_table()) => doc,
+        _ if path == LEGACY_TOOLCHAIN_FILE => Document::new(),
+        Err(e) => {
+            return Err(AuditError::Unexpected(format!(
+                "cannot parse `{path}`: {e}"
+            )))
+        }
+        Ok(_) => {
+            return Err(AuditError::Unexpected(format!(
+                "cannot parse `{path}`: `toolchain` is not a table"
+            )))
+        }
+    };
+    if !doc.contains_key("toolchain") {
+        doc["toolchain"] = table();
+    }
+    doc["toolchain"]["channel"] = value(channel);
+
+    fs::write(path, doc.to_string())
+        .map_err(|e| AuditError::Unexpected(format!("cannot write `{path}`: {e}")))?;
+
+    Ok(path)
+}
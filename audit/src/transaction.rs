@@ -0,0 +1,238 @@
+//! This file makes changes to `Cargo.lock` transactional.
+//!
+//! The lockfile is snapshotted before any change, and restored unless the audit commits,
+//! whether it fails with errors, panics, or gets interrupted by signals.
+//! Other files, e.g. manifests, can be tracked the same way before being changed,
+//! and directories, e.g. vendored crates, before being created.
+
+use std::fs;
+use std::io;
+use std::process::exit;
+use std::sync::Mutex;
+
+use cargo_metadata::MetadataCommand;
+use lazy_static::lazy_static;
+use tame_index::utils::flock::{FileLock, LockOptions};
+use tame_index::Path;
+
+use crate::error::AuditError;
+use crate::{error_print, warn_print, RUSTV};
+
+const LOCKFILE: &str = "Cargo.lock";
+/// lock file name, under the target directory of current workspace.
+const WORKSPACE_LOCK: &str = "ruf_audit.lock";
+
+lazy_static! {
+    /// Snapshot of the running transaction, used by signal handler.
+    static ref SNAPSHOT: Mutex<Option<Snapshot>> = Mutex::new(None);
+}
+
+/// Tracked files and their contents, `None` if the file did not exist,
+/// and directories created during the transaction.
+#[derive(Default)]
+struct Snapshot {
+    files: Vec<(String, Option<String>)>,
+    dirs: Vec<String>,
+}
+
+impl Snapshot {
+    fn restore(&self) -> io::Result<()> {
+        for (path, content) in &self.files {
+            match content {
+                Some(content) => fs::write(path, content)?,
+                None => match fs::remove_file(path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                },
+            }
+        }
+        for dir in &self.dirs {
+            match fs::remove_dir_all(dir) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_origin(path: &str) -> Result<Option<String>, AuditError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AuditError::Unexpected(format!(
+            "cannot snapshot `{path}`: {e}"
+        ))),
+    }
+}
+
+/// Track a file in the running transaction, so that it is restored along with the lockfile.
+pub fn track(path: &str) -> Result<(), AuditError> {
+    let mut snapshot = SNAPSHOT.lock().unwrap();
+    let Some(snapshot) = snapshot.as_mut() else {
+        return Err(AuditError::Unexpected(format!(
+            "cannot track `{path}`, no running transaction"
+        )));
+    };
+
+    if !snapshot.files.iter().any(|(tracked, _)| tracked == path) {
+        snapshot.files.push((path.to_string(), read_origin(path)?));
+    }
+
+    Ok(())
+}
+
+/// Track a directory about to be created, so that it is removed along with the lockfile restored.
+/// Unlike files, it is only removed on rollback, not by [`restore_tracked`].
+pub fn track_dir(path: &str) -> Result<(), AuditError> {
+    let mut snapshot = SNAPSHOT.lock().unwrap();
+    let Some(snapshot) = snapshot.as_mut() else {
+        return Err(AuditError::Unexpected(format!(
+            "cannot track `{path}`, no running transaction"
+        )));
+    };
+
+    if !snapshot.dirs.iter().any(|tracked| tracked == path) {
+        snapshot.dirs.push(path.to_string());
+    }
+
+    Ok(())
+}
+
+/// Current contents of files tracked in the running transaction, restored by [`restore_tracked`].
+pub fn snapshot_tracked() -> Result<Vec<(String, Option<String>)>, AuditError> {
+    let snapshot = SNAPSHOT.lock().unwrap();
+    let Some(snapshot) = snapshot.as_ref() else {
+        return Ok(vec![]);
+    };
+
+    snapshot
+        .files
+        .iter()
+        .map(|(path, _)| Ok((path.clone(), read_origin(path)?)))
+        .collect()
+}
+
+/// Restore tracked files to a snapshot taken by [`snapshot_tracked`],
+/// files tracked after it are restored to their origins. Returns the files changed.
+pub fn restore_tracked(files: &[(String, Option<String>)]) -> Result<Vec<String>, AuditError> {
+    let snapshot = SNAPSHOT.lock().unwrap();
+    let Some(snapshot) = snapshot.as_ref() else {
+        return Ok(vec![]);
+    };
+
+    let mut changed = vec![];
+    for (path, origin) in &snapshot.files {
+        let content = files
+            .iter()
+            .find(|(snapshot_path, _)| snapshot_path == path)
+            .map_or(origin, |(_, content)| content);
+        if read_origin(path)? == *content {
+            continue;
+        }
+
+        Snapshot {
+            files: vec![(path.clone(), content.clone())],
+            ..Default::default()
+        }
+        .restore()
+        .map_err(|e| AuditError::Unexpected(format!("cannot restore `{path}`: {e}")))?;
+        changed.push(path.clone());
+    }
+
+    Ok(changed)
+}
+
+pub struct LockTransaction {
+    /// exclusive lock on current workspace, released on drop.
+    _lock: FileLock,
+    /// lockfile contents before any change.
+    origin: Option<String>,
+    /// whether the transaction is finished, by commit or rollback.
+    finished: bool,
+}
+
+impl LockTransaction {
+    /// Lock current workspace and snapshot its lockfile.
+    pub fn begin() -> Result<Self, AuditError> {
+        // No deps, so that cargo does not resolve, nor write `Cargo.lock` before the snapshot.
+        let metadata = MetadataCommand::new()
+            .env("RUSTUP_TOOLCHAIN", RUSTV)
+            .no_deps()
+            .exec()
+            .map_err(|e| AuditError::Unexpected(format!("cannot load metadata: {e}")))?;
+        let lock_path = metadata.target_directory.join(WORKSPACE_LOCK);
+
+        let lock = LockOptions::new(Path::new(lock_path.as_str()))
+            .exclusive(false)
+            .lock(|path| {
+                warn_print!(
+                    false,
+                    "Blocking",
+                    &format!("waiting for file lock on {path}, another audit may be running")
+                );
+                None
+            })
+            .map_err(|e| AuditError::Unexpected(format!("cannot lock current workspace: {e}")))?;
+
+        let origin = read_origin(LOCKFILE)?;
+
+        *SNAPSHOT.lock().unwrap() = Some(Snapshot {
+            files: vec![(LOCKFILE.to_string(), origin.clone())],
+            dirs: vec![],
+        });
+        // Only the first handler can be set, later transactions share it through `SNAPSHOT`.
+        let _ = ctrlc::set_handler(|| {
+            if let Some(snapshot) = SNAPSHOT.lock().unwrap().take() {
+                if let Err(e) = snapshot.restore() {
+                    error_print!(false, &format!("cannot restore snapshot: {e}"));
+                }
+            }
+            exit(130);
+        });
+
+        Ok(Self {
+            _lock: lock,
+            origin,
+            finished: false,
+        })
+    }
+
+    /// Lockfile contents before any change.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Keep all changes made to the lockfile.
+    pub fn commit(mut self) {
+        SNAPSHOT.lock().unwrap().take();
+        self.finished = true;
+    }
+
+    /// Restore the lockfile, and other tracked files, to their snapshots.
+    pub fn rollback(mut self) -> Result<(), AuditError> {
+        self.finished = true;
+        SNAPSHOT
+            .lock()
+            .unwrap()
+            .take()
+            .map_or(Ok(()), |snapshot| snapshot.restore())
+            .map_err(|e| AuditError::Unexpected(format!("cannot restore snapshot: {e}")))
+    }
+}
+
+impl Drop for LockTransaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        // Neither committed nor rolled back, e.g. panics, so we restore.
+        if let Some(snapshot) = SNAPSHOT.lock().unwrap().take() {
+            if let Err(e) = snapshot.restore() {
+                error_print!(false, &format!("cannot restore snapshot: {e}"));
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::time::{Duration, Instant};
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+use basic_usages::ruf_check_info::CondRufs;
+
+use cargo_lock::dependency::graph::NodeIndex;
+
+use super::{Expansion, Frame, UpFix};
+use crate::audit::change_action;
+use crate::build_config::BuildConfig;
+use crate::dep_manager::DepManager;
+use crate::error::{AuditError, CandidateError};
+use crate::report::{Rejection, UpFixStep};
+use crate::{info_print, warn_print};
+
+impl<'a, 'c, 'd> UpFix<'a, 'c, 'd> {
+    pub fn new(
+        config: &'a BuildConfig<'c>,
+        dm: &'a mut DepManager<'d>,
+        issued_depnx: NodeIndex,
+        queit: bool,
+    ) -> Self {
+        let issued_pkg = &dm.graph()[issued_depnx];
+        let (issued, issued_ver) = (issued_pkg.name.to_string(), issued_pkg.version.clone());
+        let deadline = Instant::now() + Duration::from_secs(config.get_up_fix_budget());
+
+        Self {
+            config,
+            dm,
+            issued,
+            issued_ver,
+            issued_usable: vec![],
+            visited: HashSet::default(),
+            usable: HashMap::default(),
+            ruf_usable: HashMap::default(),
+            deadline,
+            queit,
+        }
+    }
+
+    /// Search for changes of parents, after which the issued crate can be fixed.
+    ///
+    /// Layers tried from current dependency tree are recorded in `path`.
+    /// The lockfile is restored on failure, changes of the closest partial result are reported.
+    pub fn search(mut self, path: &mut Vec<UpFixStep>) -> Result<(), AuditError> {
+        let origin = self.dm.snapshot()?;
+        let origin_steps = self.dm.fix_steps().len();
+        let issuednx = self.issued_nx().expect("Fatal, issued crate not found");
+        self.issued_usable = self.usable_versions(issuednx, None)?;
+        self.usable
+            .insert(vec![self.issued.clone()], self.issued_usable.clone());
+
+        let (origin_layer, changes) = match self.expand(Some(path))? {
+            Expansion::Changes(layer, changes) => (layer, changes),
+            Expansion::Fixed => {
+                return Err(AuditError::Functionality(
+                    "up fix finds nothing to relax".to_string(),
+                ))
+            }
+            Expansion::DeadEnd => {
+                return Err(AuditError::Functionality(
+                    "up fix failed, no parent can be relaxed".to_string(),
+                ))
+            }
+        };
+
+        let mut stack = vec![Frame {
+            snapshot: origin.clone(),
+            changes,
+        }];
+        // The partial result reaching the lowest layer, closest to a fix.
+        let mut best = None;
+        let mut best_layer = origin_layer;
+        let mut out_of_time = false;
+
+        while let Some(frame) = stack.last_mut() {
+            if Instant::now() > self.deadline {
+                out_of_time = true;
+                break;
+            }
+
+            let Some((name, cur_ver, fix_ver)) = frame.changes.pop() else {
+                stack.pop();
+                continue;
+            };
+            if !self.visited.insert((name.clone(), fix_ver.clone())) {
+                continue;
+            }
+
+            self.dm.restore(&frame.snapshot)?;
+            let action = change_action(&cur_ver, &fix_ver);
+            info_print!(
+                self.queit,
+                "\tUpfixing",
+                &format!("{action} {name}@{cur_ver} to {name}@{fix_ver}")
+            );
+            if let Err(e) = self
+                .dm
+                .update_pkg(&name, &cur_ver.to_string(), &fix_ver.to_string())
+            {
+                warn_print!(self.queit, "\tUpfixing", &format!("change fails: {e}"));
+                continue;
+            }
+
+            match self.expand(None) {
+                Ok(Expansion::Fixed) => return Ok(()),
+                Ok(Expansion::Changes(layer, changes)) => {
+                    let snapshot = self.dm.snapshot()?;
+                    if layer < best_layer {
+                        best_layer = layer;
+                        best = Some(
+                            self.dm.fix_steps()[origin_steps..]
+                                .iter()
+                                .map(|step| step.to_string())
+                                .collect::<Vec<_>>(),
+                        );
+                    }
+                    if stack.len() < self.config.get_up_fix_depth() {
+                        stack.push(Frame { snapshot, changes });
+                    }
+                }
+                Ok(Expansion::DeadEnd) => {}
+                Err(e) if e.is_unexpected() => return Err(e),
+                // Crates with errors are dead ends, we just backtrack.
+                Err(_) => {}
+            }
+        }
+
+        // Partial results fix nothing, so they are reported rather than applied.
+        self.dm.restore(&origin)?;
+        Err(AuditError::Functionality(match best {
+            Some(best) if out_of_time => format!(
+                "up fix runs out of time budget, closest partial result relaxes up to layer {} by: {}, not applied",
+                best_layer + 1,
+                best.join(", ")
+            ),
+            _ if out_of_time => "up fix runs out of time budget".to_string(),
+            _ => "up fix failed, no parent changes make it fixable".to_string(),
+        }))
+    }
+
+    /// Walk up the strict parents of the issued crate in current dependency tree,
+    /// until a layer whose parent can be relaxed.
+    fn expand(&mut self, mut path: Option<&mut Vec<UpFixStep>>) -> Result<Expansion, AuditError> {
+        let Some(issuednx) = self.issued_nx() else {
+            // dropped from the tree, or changed along with parents, the caller rechecks it.
+            return Ok(Expansion::Fixed);
+        };
+        // also records the strict parent.
+        let candidates = self.dm.get_candidates(issuednx)?;
+        if candidates
+            .keys()
+            .any(|ver| self.issued_usable.contains(ver))
+        {
+            return Ok(Expansion::Fixed);
+        }
+
+        let mut depnx = issuednx;
+        let mut chain = vec![self.issued.clone()];
+        for layer in 0..self.config.get_up_fix_depth() {
+            let usable_dep_vers = self.usable[&chain].clone();
+            let dep_pkg = &self.dm.graph()[depnx];
+            let (dep_name, dep) = (
+                dep_pkg.name.to_string(),
+                format!("{}@{}", dep_pkg.name, dep_pkg.version),
+            );
+
+            let Some(p_req) = self.dm.req_by(&depnx) else {
+                // already root crates
+                if let Some(path) = path.as_mut() {
+                    path.push(UpFixStep {
+                        dep,
+                        parent: None,
+                        req: None,
+                        in_db: false,
+                        no_db_entry: None,
+                        rejected: vec![],
+                        relaxable: vec![],
+                    });
+                }
+                return Ok(Expansion::DeadEnd);
+            };
+
+            let p_pkg = &self.dm.graph()[p_req];
+            let (p_name, p_ver) = (p_pkg.name.to_string(), p_pkg.version.clone());
+            let p_candidates_vers =
+                match self
+                    .dm
+                    .get_candidates_up_fix(p_req, depnx, &usable_dep_vers)
+                {
+                    Ok(p_candidates_vers) => p_candidates_vers,
+                    Err(AuditError::Candidate(CandidateError::NotInDb { reason, .. })) => {
+                        // The parent cannot be changed, neither can layers above help.
+                        if let Some(path) = path.as_mut() {
+                            let req = self
+                                .dm
+                                .get_package_reqs(&p_name, &p_ver.to_string())
+                                .ok()
+                                .and_then(|reqs| {
+                                    reqs.into_iter().find(|(name, _)| *name == dep_name)
+                                })
+                                .map(|(_, req)| req);
+                            path.push(UpFixStep {
+                                dep,
+                                parent: Some(format!("{p_name}@{p_ver}")),
+                                req,
+                                in_db: false,
+                                no_db_entry: Some(reason),
+                                rejected: vec![],
+                                relaxable: vec![],
+                            });
+                        }
+                        return Ok(Expansion::DeadEnd);
+                    }
+                    Err(e) => return Err(e),
+                };
+            let mut rejected = self.dm.take_rejected(&p_req);
+            let in_db = !p_candidates_vers.is_empty() || !rejected.is_empty();
+
+            let mut usable_vers = vec![];
+            for (ver, usable, rufs) in self.ruf_usability(&p_name, p_candidates_vers)? {
+                if !usable && !rufs.is_empty() && path.is_some() {
+                    rejected.push((ver.clone(), Rejection::UnusableRufs(rufs)));
+                }
+                if usable && !self.visited.contains(&(p_name.clone(), ver.clone())) {
+                    usable_vers.push(ver);
+                }
+            }
+
+            if let Some(path) = path.as_mut() {
+                let req = self
+                    .dm
+                    .get_package_reqs(&p_name, &p_ver.to_string())?
+                    .into_iter()
+                    .find(|(name, _)| *name == dep_name)
+                    .map(|(_, req)| req);
+                rejected.sort_by(|a, b| b.0.cmp(&a.0));
+                // Local crates cannot be changed, but their requirements can.
+                let relaxable = if self.dm.is_local(&p_name, &p_ver.to_string()) {
+                    match self.dm.admitted_by_others(depnx, p_req, &usable_dep_vers) {
+                        Ok(relaxable) => relaxable,
+                        Err(e) if e.is_unexpected() => return Err(e),
+                        Err(_) => vec![],
+                    }
+                } else {
+                    vec![]
+                };
+                path.push(UpFixStep {
+                    dep,
+                    parent: Some(format!("{p_name}@{p_ver}")),
+                    req,
+                    in_db,
+                    no_db_entry: None,
+                    rejected,
+                    relaxable,
+                });
+            }
+
+            if !usable_vers.is_empty() {
+                // preferred ones are tried first.
+                self.config.rank_candidates(&p_ver, &mut usable_vers);
+                let changes = usable_vers
+                    .into_iter()
+                    .rev()
+                    .map(|ver| (p_name.clone(), p_ver.clone(), ver))
+                    .collect();
+                return Ok(Expansion::Changes(layer, changes));
+            }
+
+            // or, we go up, where parent versions admitting usable deps are wanted.
+            let mut p_chain = chain.clone();
+            p_chain.push(p_name);
+            if !self.usable.contains_key(&p_chain) {
+                let usable_p_vers = self.usable_versions(p_req, Some((depnx, &usable_dep_vers)))?;
+                self.usable.insert(p_chain.clone(), usable_p_vers);
+            }
+            chain = p_chain;
+            depnx = p_req;
+        }
+
+        warn_print!(
+            self.queit || path.is_none(),
+            "\tUpfixing",
+            "max depth reached, try a larger `--max-depth`"
+        );
+        Ok(Expansion::DeadEnd)
+    }
+
+    /// Node of the issued crate in current dependency tree, matched by name and version,
+    /// since node indices change along with the tree.
+    fn issued_nx(&self) -> Option<NodeIndex> {
+        let graph = self.dm.graph();
+        graph.node_indices().find(|nx| {
+            graph[*nx].name.as_str() == self.issued && graph[*nx].version == self.issued_ver
+        })
+    }
+
+    /// Versions of a package in database with usable rufs, regardless of its parents' version req.
+    /// If `dep` is given, its req on the dep shall also admit one of the usable dep versions.
+    fn usable_versions(
+        &mut self,
+        pkgnx: NodeIndex,
+        dep: Option<(NodeIndex, &[Version])>,
+    ) -> Result<Vec<Version>, AuditError> {
+        let dm = &*self.dm;
+        let graph = dm.graph();
+        let pkg = &graph[pkgnx];
+        let name = pkg.name.as_str();
+        if dm.is_local(name, &pkg.version.to_string()) {
+            // local crates cannot be changed.
+            return Ok(vec![pkg.version.clone()]);
+        }
+
+        let mut vers = vec![];
+        for (ver, cond_rufs) in dm.get_versions(pkgnx)? {
+            if let Some((depnx, usable_dep_vers)) = dep {
+                let dep_name = graph[depnx].name.as_str();
+                let reqs = match dm.get_package_reqs(name, &ver.to_string()) {
+                    Ok(reqs) => reqs,
+                    // versions missing in index cannot be used.
+                    Err(AuditError::Candidate(_)) => continue,
+                    Err(e) => return Err(e),
+                };
+                if let Some((_, req)) = reqs.iter().find(|(name, _)| name == dep_name) {
+                    if !usable_dep_vers.iter().any(|dep_ver| req.matches(dep_ver)) {
+                        continue;
+                    }
+                }
+            }
+            vers.push((ver, cond_rufs));
+        }
+
+        let name = name.to_string();
+        Ok(self
+            .ruf_usability(&name, vers)?
+            .into_iter()
+            .filter(|(_, usable, _)| *usable)
+            .map(|(ver, _, _)| ver)
+            .collect())
+    }
+
+    /// Ruf usability of versions of a package, those not cached are checked at once.
+    /// Unusable rufs are given for versions newly checked.
+    fn ruf_usability(
+        &mut self,
+        name: &str,
+        vers: impl IntoIterator<Item = (Version, CondRufs)>,
+    ) -> Result<Vec<(Version, bool, Vec<String>)>, AuditError> {
+        let mut checked = vec![];
+        let mut unchecked = vec![];
+        for (ver, cond_rufs) in vers {
+            match self.ruf_usable.get(&(name.to_string(), ver.clone())) {
+                Some(usable) => checked.push((ver, *usable, vec![])),
+                None => unchecked.push((ver, cond_rufs)),
+            }
+        }
+
+        let (unchecked_vers, unchecked_rufs): (Vec<_>, Vec<_>) = unchecked.into_iter().unzip();
+        let jobs = unchecked_vers
+            .iter()
+            .zip(unchecked_rufs)
+            .map(|(ver, rufs)| (name, ver, rufs, None))
+            .collect();
+        let used_rufs = self.config.filter_rufs_many(jobs)?;
+        for (ver, used_rufs) in unchecked_vers.into_iter().zip(used_rufs) {
+            let usable = self.config.rufs_usable(&used_rufs);
+            let rufs = if usable {
+                vec![]
+            } else {
+                self.config.unusable_rufs(&used_rufs)
+            };
+            self.ruf_usable
+                .insert((name.to_string(), ver.clone()), usable);
+            checked.push((ver, usable, rufs));
+        }
+
+        Ok(checked)
+    }
+}
@@ -0,0 +1,56 @@
+//! This module relaxes the parents of an issued crate, when none of its usable versions are allowed by them.
+//!
+//! Each state is a dependency tree. From a state, we walk up the strict parents of the issued crate,
+//! and the changes to try are the candidates at the lowest layer whose parent can be relaxed.
+//! States are searched depth-first with backtracking, changes already tried are not repeated,
+//! and the search is bounded by layers, changes in a row and time.
+
+use std::time::Instant;
+
+use basic_usages::external::fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use basic_usages::external::semver::Version;
+
+use crate::build_config::BuildConfig;
+use crate::dep_manager::{DepManager, LockSnapshot};
+
+mod r#impl;
+
+pub struct UpFix<'a, 'c, 'd> {
+    config: &'a BuildConfig<'c>,
+    dm: &'a mut DepManager<'d>,
+
+    /// name of the issued crate.
+    issued: String,
+    /// version of the issued crate, other versions of it may coexist in the tree.
+    issued_ver: Version,
+    /// versions of the issued crate with usable rufs.
+    issued_usable: Vec<Version>,
+
+    /// changes already tried, as `(name, new version)`.
+    visited: HashSet<(String, Version)>,
+    /// usable versions of packages, keyed by the crate names from the issued one up to the package.
+    usable: HashMap<Vec<String>, Vec<Version>>,
+    /// ruf usability of crate versions.
+    ruf_usable: HashMap<(String, Version), bool>,
+
+    deadline: Instant,
+    queit: bool,
+}
+
+/// A dependency tree during search, and the changes still to try on it.
+struct Frame {
+    /// lockfile of this state, restored before each change.
+    snapshot: LockSnapshot,
+    /// changes as `(name, current version, new version)`, the next one lies at the end.
+    changes: Vec<(String, Version, Version)>,
+}
+
+/// What to do with a state.
+enum Expansion {
+    /// The issued crate can be fixed by changing itself.
+    Fixed,
+    /// Changes at the given layer, the lower the closer to a fix.
+    Changes(usize, Vec<(String, Version, Version)>),
+    /// No parent can be relaxed within the depth.
+    DeadEnd,
+}
@@ -1,4 +1,4 @@
+pub use fxhash;
 pub use semver;
 pub use serde;
 pub use serde_json;
-pub use fxhash;
\ No newline at end of file
@@ -1,6 +1,6 @@
 pub mod ruf_check_info;
-pub mod ruf_lifetime;
 pub mod ruf_db_usage;
+pub mod ruf_lifetime;
 pub mod rustc_version;
 
-pub mod external;
\ No newline at end of file
+pub mod external;
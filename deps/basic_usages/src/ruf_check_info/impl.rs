@@ -22,7 +22,6 @@ impl UsedRufs {
     pub fn iter(&self) -> std::slice::Iter<'_, String> {
         self.0.iter()
     }
-
 }
 
 impl CondRufs {
@@ -47,7 +46,6 @@ impl CondRufs {
     }
 }
 
-
 impl IntoIterator for UsedRufs {
     type Item = String;
     type IntoIter = std::vec::IntoIter<Self::Item>;
@@ -68,10 +66,12 @@ impl IntoIterator for CondRufs {
 
 impl RufStatus {
     pub fn is_usable(&self) -> bool {
-        match self {
-            Self::Removed | Self::Unknown => false,
-            _ => true,
-        }
+        !matches!(self, Self::Removed | Self::Unknown)
+    }
+
+    /// Whether the ruf is stabilized, which is the only usable status on stable rustc.
+    pub fn is_stable(&self) -> bool {
+        matches!(self, Self::Accepted)
     }
 }
 
@@ -85,15 +85,15 @@ impl Display for CheckInfo {
     }
 }
 
-impl Into<String> for CheckInfo {
-    fn into(self) -> String {
-        format!("{self}")
+impl From<CheckInfo> for String {
+    fn from(val: CheckInfo) -> Self {
+        format!("{val}")
     }
 }
 
 impl From<&str> for CheckInfo {
     fn from(value: &str) -> Self {
-        serde_json::from_str(&value).expect("Fatal, deserialize fails")
+        serde_json::from_str(value).expect("Fatal, deserialize fails")
     }
 }
 
@@ -107,19 +107,18 @@ impl Display for UsedRufs {
     }
 }
 
-impl Into<String> for UsedRufs {
-    fn into(self) -> String {
-        format!("{self}")
+impl From<UsedRufs> for String {
+    fn from(val: UsedRufs) -> Self {
+        format!("{val}")
     }
 }
 
 impl From<&str> for UsedRufs {
     fn from(value: &str) -> Self {
-        serde_json::from_str(&value).expect("Fatal, deserialize fails")
+        serde_json::from_str(value).expect("Fatal, deserialize fails")
     }
 }
 
-
 impl From<&str> for RufStatus {
     fn from(value: &str) -> Self {
         match value {
@@ -133,6 +132,19 @@ impl From<&str> for RufStatus {
     }
 }
 
+impl Display for RufStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            RufStatus::Unknown => "unknown",
+            RufStatus::Active => "active",
+            RufStatus::Incomplete => "incomplete",
+            RufStatus::Accepted => "accepted",
+            RufStatus::Removed => "removed",
+        };
+        write!(f, "{status}")
+    }
+}
+
 impl From<u32> for RufStatus {
     fn from(value: u32) -> Self {
         match value {
@@ -2,13 +2,13 @@ use serde::{Deserialize, Serialize};
 
 mod r#impl;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CondRuf {
     pub cond: Option<String>,
     pub feature: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CondRufs(Vec<CondRuf>);
 
 #[derive(Debug)]
@@ -20,12 +20,27 @@ pub enum RufStatus {
     Removed,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UsedRufs(Vec<String>);
 
+/// A scan job in scanner batch mode, checking rufs enabled in `src` under `cfgs`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanJob {
+    pub id: usize,
+    pub cfgs: Vec<String>,
+    pub src: String,
+}
+
+/// Result of a scan job, with the same id.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct CheckInfo{
+pub struct ScanResult {
+    pub id: usize,
+    pub used_rufs: Result<UsedRufs, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckInfo {
     pub crate_name: String,
     pub used_rufs: UsedRufs,
     pub cfg: Vec<String>,
-}
\ No newline at end of file
+}
@@ -39,7 +39,7 @@ pub fn get_crate_id_with_name(crate_name: &str) -> Result<i32, String> {
         )
         .map_err(|e| e.to_string())?;
 
-    if crate_id.len() == 0 {
+    if crate_id.is_empty() {
         return Err(format!("No crate with name {} found", crate_name));
     }
 
@@ -65,10 +65,7 @@ pub fn get_rufs_with_crate_id(crate_id: i32) -> Result<HashMap<Version, CondRufs
                 let cond = row.try_get::<usize, String>(3).map_err(|e| e.to_string())?;
                 let cond = if cond.is_empty() { None } else { Some(cond) };
 
-                let ruf = CondRuf {
-                    cond: cond,
-                    feature: ruf,
-                };
+                let ruf = CondRuf { cond, feature: ruf };
 
                 dep_rufs
                     .entry(ver)
@@ -81,6 +78,25 @@ pub fn get_rufs_with_crate_id(crate_id: i32) -> Result<HashMap<Version, CondRufs
     Ok(dep_rufs)
 }
 
+/// Identity of current database contents, which changes once rufs are added or removed.
+#[allow(unused)]
+pub fn get_db_snapshot() -> Result<String, String> {
+    let rows = CONN
+        .lock()
+        .unwrap()
+        .query(
+            "SELECT COUNT(*), COALESCE(MAX(id), 0) FROM version_ruf",
+            &[],
+        )
+        .map_err(|e| e.to_string())?;
+
+    let row = rows.first().ok_or("No database snapshot found")?;
+    let count = row.try_get::<usize, i64>(0).map_err(|e| e.to_string())?;
+    let max_id = row.try_get::<usize, i32>(1).map_err(|e| e.to_string())?;
+
+    Ok(format!("{max_id}-{count}"))
+}
+
 #[allow(unused)]
 pub fn get_rufs_with_crate_name(crate_name: &str) -> Result<HashMap<Version, CondRufs>, String> {
     let crate_id = get_crate_id_with_name(crate_name)?;
@@ -13,6 +13,31 @@ pub fn get_nightly_version(rustc_version: u32) -> &'static str {
     RUSTC_VERSION[&rustc_version]
 }
 
+/// Resolve rustc version from a toolchain, which is either a nightly (`nightly-2021-09-09`),
+/// or a rustc version (`1.56` or `1.56.0`).
+///
+/// A nightly is resolved to the latest rustc version released before it.
+pub fn get_rustc_version(toolchain: &str) -> Option<u32> {
+    if let Some(date) = toolchain.strip_prefix("nightly-") {
+        if date.len() != "2021-09-09".len() {
+            return None;
+        }
+
+        return (0..RUSTC_VER_NUM as u32)
+            .rev()
+            .find(|ver| RUSTC_VERSION[ver] <= toolchain);
+    }
+
+    let minor = toolchain
+        .strip_prefix("1.")?
+        .split('.')
+        .next()?
+        .parse::<u32>()
+        .ok()?;
+
+    ((minor as usize) < RUSTC_VER_NUM).then_some(minor)
+}
+
 fn get_nightly_versions_raw() -> FxHashMap<u32, &'static str> {
     // Notice: this rust version and dates are based on our test machine:
     // Linux ubuntu-7070 6.5.0-18-generic #18~22.04.1-Ubuntu SMP PREEMPT_DYNAMIC Wed Feb  7 11:40:03 UTC 2 x86_64 x86_64 x86_64 GNU/Linux
@@ -95,7 +120,7 @@ fn get_nightly_versions_raw() -> FxHashMap<u32, &'static str> {
 #[allow(unused)]
 fn install_toolchains() {
     for i in (0..=63).rev() {
-        let name =  get_nightly_version(i);
+        let name = get_nightly_version(i);
 
         let mut cmd = std::process::Command::new("rustup");
         cmd.args(["toolchain", "install", &name, "--profile", "minimal"]);
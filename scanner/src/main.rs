@@ -39,6 +39,11 @@ fn main() {
         "Print full check information, or only print used rufs",
     );
     opts.optopt("r", "rustc", "Run rustc after scan", "VALUE");
+    opts.optflag(
+        "b",
+        "batch",
+        "Scan jobs from stdin, one json per line, with rustc args shared",
+    );
 
     let split_index = args.iter().position(|arg| arg == "--");
 
@@ -60,11 +65,15 @@ fn main() {
             exit(-1);
         }
     };
-    if matches.opt_present("h") || rustc_args.is_empty() {
+    if matches.opt_present("h") || (rustc_args.is_empty() && !matches.opt_present("b")) {
         show_help();
     }
     let output_buildinfo = matches.opt_present("c");
 
+    if matches.opt_present("b") {
+        exit(rustc76::run_batch(&rustc_args));
+    }
+
     // run our scanner
     let exit_code = rustc76::run_rustc(&rustc_args, output_buildinfo);
     if exit_code != 0 {
@@ -5,10 +5,11 @@ use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use std::sync::Arc;
 
-use basic_usages::ruf_check_info::{CheckInfo, UsedRufs};
+use basic_usages::external::serde_json;
+use basic_usages::ruf_check_info::{CheckInfo, ScanJob, ScanResult, UsedRufs};
 use rustc_driver::{
-    args, catch_with_exit_code, diagnostics_registry, handle_options, Callbacks, Compilation,
-    TimePassesCallbacks, DEFAULT_LOCALE_RESOURCES,
+    args, catch_fatal_errors, catch_with_exit_code, diagnostics_registry, handle_options,
+    Callbacks, Compilation, TimePassesCallbacks, DEFAULT_LOCALE_RESOURCES,
 };
 
 use rustc_ast::{self as ast, Attribute};
@@ -21,26 +22,76 @@ use rustc_session::EarlyErrorHandler;
 use rustc_span::symbol::sym;
 use rustc_span::FileName;
 
-pub fn run_rustc(args: &Vec<String>, output_buildinfo: bool) -> i32 {
+pub fn run_rustc(args: &[String], output_buildinfo: bool) -> i32 {
     let mut callbacks = TimePassesCallbacks::default();
 
-    let exit_code = catch_with_exit_code(|| {
+    catch_with_exit_code(|| {
         if output_buildinfo {
-            run_compiler(true, args, &mut callbacks)
+            run_compiler(true, args, &mut callbacks, None).map(|_| ())
         } else {
-            run_compiler(false, args, &mut callbacks)
+            run_compiler(false, args, &mut callbacks, None).map(|_| ())
         }
-    });
+    })
+}
 
-    exit_code
+/// Run scan jobs read from stdin, one json per line, in a single process,
+/// so that the compiler is loaded only once. Results are printed one json per line.
+pub fn run_batch(args: &[String]) -> i32 {
+    let mut input = String::new();
+    if io::stdin().read_to_string(&mut input).is_err() {
+        eprintln!("couldn't read scan jobs from stdin");
+        return 1;
+    }
+
+    for line in input.lines().filter(|line| !line.trim().is_empty()) {
+        let job: ScanJob = match serde_json::from_str(line) {
+            Ok(job) => job,
+            Err(e) => {
+                eprintln!("invalid scan job: {e}");
+                return 1;
+            }
+        };
+
+        let result = run_job(args, job);
+        println!(
+            "{}",
+            serde_json::to_string(&result).expect("Fatal, cannot serialize scan result")
+        );
+    }
+
+    0
+}
+
+fn run_job(args: &[String], job: ScanJob) -> ScanResult {
+    let mut job_args = args.to_vec();
+    for cfg in job.cfgs {
+        job_args.push("--cfg".to_string());
+        job_args.push(cfg);
+    }
+    job_args.push("-".to_string());
+
+    let mut callbacks = TimePassesCallbacks::default();
+    let used_rufs =
+        catch_fatal_errors(|| run_compiler(false, &job_args, &mut callbacks, Some(job.src)))
+            .map_err(|_| "scanner aborts".to_string())
+            .and_then(|res| res.map_err(|_| "cannot scan source".to_string()))
+            .map(UsedRufs::new);
+
+    ScanResult {
+        id: job.id,
+        used_rufs,
+    }
 }
 
 // Is it correct?
+/// Returns used rufs. Sources of batch jobs are given as `src`,
+/// and their results are returned rather than printed.
 fn run_compiler(
     output_buildinfo: bool,
     at_args: &[String],
     callbacks: &mut (dyn Callbacks + Send),
-) -> interface::Result<()> {
+    src: Option<String>,
+) -> interface::Result<Vec<String>> {
     // println!("AUDIT DEBUG: args: {at_args:?}");
     let mut default_handler = EarlyErrorHandler::new(ErrorOutputType::default());
 
@@ -49,7 +100,7 @@ fn run_compiler(
     let args = args::arg_expand_all(&default_handler, at_args);
 
     let Some(matches) = handle_options(&default_handler, &args) else {
-        return Ok(());
+        return Ok(vec![]);
     };
 
     let sopts = config::build_session_options(&mut default_handler, &matches);
@@ -78,7 +129,15 @@ fn run_compiler(
         expanded_args: args,
     };
 
-    let has_input = match make_input(&default_handler, &matches.free) {
+    let batch = src.is_some();
+    let input = match src {
+        Some(src) => Ok(Some(Input::Str {
+            name: FileName::anon_source_code(&src),
+            input: src,
+        })),
+        None => make_input(&default_handler, &matches.free),
+    };
+    let has_input = match input {
         Err(reported) => return Err(reported),
         Ok(Some(input)) => {
             config.input = input;
@@ -142,27 +201,31 @@ fn run_compiler(
             Ok(Some(f))
         })?;
 
-        let used_rufs = features
+        let used_rufs: Vec<String> = features
             .map(|mut feats| feats.drain().map(|sym| sym.to_string()).collect())
             .unwrap_or(Vec::new());
 
+        if batch {
+            return Ok(used_rufs);
+        }
+
         if output_buildinfo {
             assert!(crate_name.len() == 1, "Fatal, fetch crate name errors");
             let build_info = CheckInfo {
                 crate_name: crate_name.first().unwrap().clone(),
-                used_rufs: UsedRufs::new(used_rufs),
+                used_rufs: UsedRufs::new(used_rufs.clone()),
                 cfg: matches.opt_strs("cfg"),
             };
 
             println!("{}", build_info);
         } else {
-            println!("{}", UsedRufs::new(used_rufs));
+            println!("{}", UsedRufs::new(used_rufs.clone()));
         }
 
-        Ok(())
+        Ok(used_rufs)
     });
 
-    return res;
+    res
 }
 
 /// Extract input (string or file and optional path) from matches.
@@ -188,7 +251,8 @@ fn make_input(
                     "when UNSTABLE_RUSTDOC_TEST_PATH is set \
                                     UNSTABLE_RUSTDOC_TEST_LINE also needs to be set",
                 );
-                let line = isize::from_str_radix(&line, 10)
+                let line = line
+                    .parse::<isize>()
                     .expect("UNSTABLE_RUSTDOC_TEST_LINE needs to be an number");
                 let file_name = FileName::doc_test_source_code(PathBuf::from(path), line);
                 Ok(Some(Input::Str {
@@ -271,7 +335,7 @@ fn run_actual_rustc(
             .write_all(empty_rs.as_bytes())
             .map_err(|e| format!("cannot write empty fn into rustc stdin: {e}"))?;
     } else {
-        return Err(format!("cannot open rustc stdin"));
+        return Err("cannot open rustc stdin".to_string());
     }
 
     let output = rustc